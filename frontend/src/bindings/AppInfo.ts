@@ -1,3 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Rating } from "./Rating";
 
-export interface AppInfo { app_id: string, version: number, date: bigint, name: string, source_code_url: string | null, image: string, description: string, size: bigint, }
\ No newline at end of file
+export interface AppInfo { app_id: string, version: number, date: bigint, name: string, source_code_url: string | null, image: string, description: string, size: bigint, critical: boolean, downloads?: number, rating?: Rating, }
\ No newline at end of file
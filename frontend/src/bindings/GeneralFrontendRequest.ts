@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export type GeneralFrontendRequest = { type: "UpdateWebxdc" };
\ No newline at end of file
+export type GeneralFrontendRequest = { type: "UpdateWebxdc" } | { type: "Hello", protocol_version: number, capabilities: Array<string>, };
\ No newline at end of file
@@ -1,3 +1,5 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { Feature } from "./Feature";
+import type { StoreXdcUpdate } from "./StoreXdcUpdate";
 
-export type GeneralFrontendResponse = { type: "Outdated", critical: boolean, version: number, } | { type: "UpdateSent" };
\ No newline at end of file
+export type GeneralFrontendResponse = { type: "Outdated", critical: boolean, version: number, } | { type: "UpdateSent" } | { type: "Welcome", protocol_version: number, features: Array<Feature>, update: StoreXdcUpdate | null, };
\ No newline at end of file
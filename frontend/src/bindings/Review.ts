@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface Review { id: number, app_id: string, version: number, stars: number, comment: string | null, date: bigint, }
\ No newline at end of file
@@ -1,3 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { UpdatePolicy } from "./UpdatePolicy";
 
-export type StoreRequest = { Update: { serial: number, apps: Array<[string, number]>, } } | { Download: { app_id: string, } };
\ No newline at end of file
+export type StoreRequest = { Update: { serial: number, apps: Array<[string, number]> | null, device: string | null, language: string | null, } } | { Download: { app_id: string, device: string | null, } } | { Subscribe: { app_id: string, } } | { Unsubscribe: { app_id: string, } } | { Rate: { app_id: string, stars: number, comment: string | null, } } | { GetReviews: { app_id: string, } } | { Report: { app_id: string, reason: string, } } | { SetUpdatePolicy: { policy: UpdatePolicy, } };
\ No newline at end of file
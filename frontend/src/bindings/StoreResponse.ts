@@ -1,4 +1,9 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 import type { AppInfo } from "./AppInfo";
+import type { AppStats } from "./AppStats";
+import type { Rating } from "./Rating";
+import type { Review } from "./Review";
+import type { StoreError } from "./StoreError";
+import type { UpdatePolicy } from "./UpdatePolicy";
 
-export type StoreResponse = { type: "DownloadOkay", app_id: string, name: string, data: string, } | { type: "DownloadError", app_id: string, error: string, } | { type: "Update", app_infos: Array<AppInfo>, serial: number, updating: Array<string>, };
\ No newline at end of file
+export type StoreResponse = { type: "DownloadOkay", app_id: string, name: string, data: string, } | { type: "DownloadError", app_id: string, error: StoreError, } | { type: "Update", app_infos: Array<AppInfo>, serial: number, updating: Array<string>, removed: Array<string>, stats: Record<string, AppStats>, installed: Array<[string, number]>, update_policy: UpdatePolicy, } | { type: "UpdatesAvailable", app_ids: Array<string>, } | { type: "UpdatePolicy", policy: UpdatePolicy, } | { type: "Subscriptions", app_ids: Array<string>, } | { type: "RateOkay", app_id: string, rating: Rating, } | { type: "RateError", app_id: string, error: StoreError, } | { type: "Reviews", app_id: string, reviews: Array<Review>, } | { type: "ReportOkay", app_id: string, } | { type: "ReportError", app_id: string, error: StoreError, } | { type: "Error", error: StoreError, };
\ No newline at end of file
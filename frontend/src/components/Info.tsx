@@ -1,11 +1,14 @@
 import { type Component, Show } from 'solid-js'
 import { formatDistanceToNow } from 'date-fns'
+import type { UpdatePolicy } from '../bindings/UpdatePolicy'
 
 interface Props {
   version: number
   updating: boolean
   last_update: Date
+  policy: UpdatePolicy
   onUpdate: () => void
+  onPolicyChange: (policy: UpdatePolicy) => void
   onClose: () => void
 }
 
@@ -35,6 +38,15 @@ const Info: Component<Props> = (props) => {
             </div>
           </Show>
         </div>
+        <div class="stretch-item">
+          <p>App updates: </p>
+          <select class="px-2 unimportant btn" value={props.policy} onClick={event => event.stopPropagation()}
+            onChange={event => props.onPolicyChange((event.target as HTMLSelectElement).value as UpdatePolicy)}>
+            <option value="Auto">Automatic</option>
+            <option value="Ask">Ask first</option>
+            <option value="CriticalOnly">Only critical automatic</option>
+          </select>
+        </div>
       </div>
     </div>
   )
@@ -1,5 +1,5 @@
 import { For, render } from 'solid-js/web'
-import { Match, Show, Switch, createEffect, createMemo, createSignal } from 'solid-js'
+import { Match, Show, Switch, createEffect, createMemo, createSignal, onMount } from 'solid-js'
 import { createStore } from 'solid-js/store'
 import '~/index.sass'
 import 'virtual:uno.css'
@@ -10,12 +10,15 @@ import Fuse from 'fuse.js'
 import Info from './components/Info'
 import OutdatedView from './components/Outdated'
 import type { StoreRequest } from '~/bindings/StoreRequest'
+import type { GeneralFrontendRequest } from '~/bindings/GeneralFrontendRequest'
+import type { UpdatePolicy } from '~/bindings/UpdatePolicy'
 
 import type { StoreResponse } from '~/bindings/StoreResponse'
 import { AppInfoDB } from '~/db/store_db'
 import { to_app_infos_by_id, updateHandler } from '~/store-logic'
 import { AppState } from '~/types'
-import type { AppInfoWithState, AppInfosById } from '~/types'
+import { PROTOCOL_VERSION } from '~/utils'
+import type { AppInfoWithState, AppInfosById, Envelope } from '~/types'
 import mock from '~/mock'
 import type { ReceivedStatusUpdate } from '~/webxdc'
 
@@ -69,6 +72,14 @@ function AppInfoModal(item: AppInfoWithState, onDownload: () => void, onForward:
               <div class="i-material-symbols:forward text-white"></div>
             </button>
           </Match>
+          <Match when={item.state === AppState.UpdateAvailable}>
+            <button class="send-button bg-blue-500" onClick={onDownload}>
+              <div class="i-material-symbols:upgrade text-white"></div>
+            </button>
+            <button class="send-button bg-green-500" onClick={onForward}>
+              <div class="i-material-symbols:forward text-white"></div>
+            </button>
+          </Match>
         </Switch>
       </div >
       {
@@ -80,7 +91,7 @@ function AppInfoModal(item: AppInfoWithState, onDownload: () => void, onForward:
               <p class="text-sm text-gray-600"><span class="font-bold"> Size: </span>{(Number(item.size) / 1000).toFixed(1).toString()} kb</p>
               <p class="break-all text-sm text-gray-600"><span class="font-bold"> Source-code: </span>{item.source_code_url}</p>
             </div>
-            {(item.state === AppState.Received || item.state === AppState.Updating) && <button class="self-center btn" onClick={onRemove}>Remove from cache</button>}
+            {(item.state === AppState.Received || item.state === AppState.Updating || item.state === AppState.UpdateAvailable) && <button class="self-center btn" onClick={onRemove}>Remove from cache</button>}
           </div>
         )
       }
@@ -134,16 +145,26 @@ const Store: Component = () => {
   const [updateReceived, setUpdateReceived] = useStorage('update-received', false)
   const [lastUpdateSerial, setlastUpdateSerial] = useStorage('last-update-serial', 0) // Last serial to initialize updateListener
   const [lastUpdate, setlastUpdate] = useStorage('last-update', new Date())
+  const [device] = useStorage('device', Math.random().toString(36).slice(2, 12)) // Random ID of this device
+  const [reportedApps, setReportedApps] = useStorage('reported-apps', '') // Cached apps last sent to the bot
+  const [updatePolicy, setUpdatePolicy] = useStorage<UpdatePolicy>('update-policy', 'Auto') // How the bot delivers updates of cached apps
   const [isUpdating, setIsUpdating] = createSignal(false)
   const [query, setSearch] = createSignal('')
   const [showInfo, setShowInfo] = createSignal(false) // Show the commit hash when heading was clicked
   const cached = createMemo(() => Object.values(appInfo).filter(app_info => app_info.state !== AppState.Initial))
 
-  // automatically update the app list
-  const past_time = Math.abs(new Date().getTime() - lastUpdate().getTime()) / 1000
-  if (appInfo === undefined || (past_time > 60 * 60)) {
-    update()
-  }
+  onMount(() => {
+    // announce the protocol version once, the bot answers with Welcome or Outdated
+    window.webxdc.sendUpdate({
+      payload: { type: 'Hello', protocol_version: PROTOCOL_VERSION, capabilities: ['TypedErrors'] } as GeneralFrontendRequest,
+    }, '')
+
+    // automatically update the app list
+    const past_time = Math.abs(new Date().getTime() - lastUpdate().getTime()) / 1000
+    if (appInfo === undefined || (past_time > 60 * 60)) {
+      update()
+    }
+  })
 
   const db = new AppInfoDB('webxdc')
   // This is for now _not_ synchronized with the update receival so a delayed
@@ -158,24 +179,39 @@ const Store: Component = () => {
     }
   })
 
-  window.webxdc.setUpdateListener(async (resp: ReceivedStatusUpdate<UpdateResponse | DownloadResponseOkay>) => {
-    updateHandler(resp.payload, db, appInfo, setAppInfo, setlastUpdateSerial, setIsUpdating, setlastUpdate, setUpdateNeeded, setUpdateReceived)
+  // requests sent by this device by their ID, other devices of the user share the responses
+  const pending = new Map<string, StoreRequest>()
+
+  window.webxdc.setUpdateListener(async (resp: ReceivedStatusUpdate<Envelope<UpdateResponse | DownloadResponseOkay>>) => {
+    updateHandler(resp.payload, pending, db, appInfo, setAppInfo, setlastUpdateSerial, setIsUpdating, setlastUpdate, setUpdateNeeded, setUpdateReceived, setUpdatePolicy)
     setlastSerial(resp.serial)
   }, lastSerial())
 
+  function sendRequest(request: StoreRequest) {
+    const request_id = `${Date.now().toString(36)}-${Math.random().toString(36).slice(2, 10)}`
+    pending.set(request_id, request)
+    window.webxdc.sendUpdate({
+      payload: { ...request, request_id } as Envelope<StoreRequest>,
+    }, '')
+  }
+
   async function update() {
     setIsUpdating(true)
     const cached_apps = cached().map(app_info => ([app_info.app_id, app_info.version] as [string, number]))
-    window.webxdc.sendUpdate({
-      payload: { Update: { serial: lastUpdateSerial(), apps: cached_apps } } as StoreRequest,
-    }, '')
+    // the bot remembers the cached apps of this device, so they are only sent when they changed
+    const reported = JSON.stringify(cached_apps)
+    const apps = reported !== reportedApps() ? cached_apps : null
+    setReportedApps(reported)
+    sendRequest({ Update: { serial: lastUpdateSerial(), apps, device: device(), language: navigator.language } })
   }
 
   async function handleDownload(app_id: string) {
     setAppInfo(app_id, 'state', AppState.Downloading)
-    window.webxdc.sendUpdate({
-      payload: { Download: { app_id } } as StoreRequest,
-    }, '')
+    sendRequest({ Download: { app_id, device: device() } })
+  }
+
+  function handlePolicyChange(policy: UpdatePolicy) {
+    sendRequest({ SetUpdatePolicy: { policy } })
   }
 
   async function handleForward(app_id: string) {
@@ -243,6 +279,8 @@ const Store: Component = () => {
           last_update={lastUpdate()}
           onClose={() => setShowInfo(false)}
           onUpdate={update}
+          onPolicyChange={handlePolicyChange}
+          policy={updatePolicy()}
           updating={isUpdating()}
           version={import.meta.env.VITE_COMMIT} />
       </Show>
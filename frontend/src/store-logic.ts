@@ -1,15 +1,20 @@
 import type { Setter } from 'solid-js'
 import type { SetStoreFunction } from 'solid-js/store'
 import { produce } from 'solid-js/store'
-import type { AppInfoWithState, AppInfosById } from './types'
+import type { AppInfoWithState, AppInfosById, Envelope } from './types'
 import { AppState } from './types'
+import type { StoreRequest } from './bindings/StoreRequest'
 import type { StoreResponse } from './bindings/StoreResponse'
+import type { UpdatePolicy } from './bindings/UpdatePolicy'
 import type { AppInfoDB } from './db/store_db'
-import { isOutdatedResponse, isUpdateSendResponse as isUpdateSentResponse } from './utils'
+import { isOutdatedResponse, isUpdateSendResponse as isUpdateSentResponse, isWelcomeResponse } from './utils'
 
 export type DownloadResponseOkay = Extract<StoreResponse, { type: 'DownloadOkay' }>
 export type DownloadResponseError = Extract<StoreResponse, { type: 'DownloadError' }>
 export type UpdateResponse = Extract<StoreResponse, { type: 'Update' }>
+export type ErrorResponse = Extract<StoreResponse, { type: 'Error' }>
+export type UpdatesAvailableResponse = Extract<StoreResponse, { type: 'UpdatesAvailable' }>
+export type UpdatePolicyResponse = Extract<StoreResponse, { type: 'UpdatePolicy' }>
 
 function isDownloadResponseOkay(p: any): p is DownloadResponseOkay {
   return p.type === 'DownloadOkay'
@@ -23,6 +28,18 @@ function isUpdateResponse(p: any): p is UpdateResponse {
   return p.type === 'Update'
 }
 
+function isErrorResponse(p: any): p is ErrorResponse {
+  return p.type === 'Error'
+}
+
+function isUpdatesAvailableResponse(p: any): p is UpdatesAvailableResponse {
+  return p.type === 'UpdatesAvailable'
+}
+
+function isUpdatePolicyResponse(p: any): p is UpdatePolicyResponse {
+  return p.type === 'UpdatePolicy'
+}
+
 function isEmpty(obj: any) {
   for (const prop in obj) {
     if (Object.prototype.hasOwnProperty.call(obj, prop))
@@ -39,7 +56,8 @@ export function to_app_infos_by_id<T extends { app_id: string }>(app_infos: T[])
 }
 
 export async function updateHandler(
-  payload: object,
+  payload: Envelope<object>,
+  pending: Map<string, StoreRequest>,
   db: AppInfoDB,
   appInfo: AppInfosById,
   setAppInfo: SetStoreFunction<AppInfosById>,
@@ -48,7 +66,11 @@ export async function updateHandler(
   setlastUpdate: Setter<Date>,
   setUpdateNeeded: Setter<boolean>,
   setUpdateReceived: Setter<boolean>,
+  setUpdatePolicy: Setter<UpdatePolicy>,
 ) {
+  // the request of this device the payload responds to, if any
+  const request = payload.request_id !== undefined ? pending.get(payload.request_id) : undefined
+
   if (isUpdateResponse(payload)) {
     if (isEmpty(appInfo)) {
       // initially write the newest update to state
@@ -84,7 +106,28 @@ export async function updateHandler(
       await db.updateMultiple(updated.map(key => ({ ...app_infos[key], state: appInfo[key].state !== AppState.Initial ? AppState.Updating : AppState.Initial })))
     }
 
+    // apps hidden by the store admins
+    if (payload.removed.length > 0) {
+      console.log('Removing hidden apps', payload.removed)
+      setAppInfo(produce((s) => {
+        for (const key of payload.removed)
+          delete s[key]
+      }))
+      await db.remove_multiple_app_infos(payload.removed)
+    }
+
+    // download counts and ratings change without a new serial, so they are sent for all apps
+    setAppInfo(produce((s) => {
+      for (const [key, stats] of Object.entries(payload.stats)) {
+        if (s[key] !== undefined)
+          Object.assign(s[key], stats)
+      }
+    }))
+
+    if (request !== undefined && 'Update' in request)
+      pending.delete(payload.request_id!)
     setlastUpdateSerial(payload.serial)
+    setUpdatePolicy(payload.update_policy)
     setIsUpdating(false)
     setlastUpdate(new Date())
   }
@@ -94,14 +137,48 @@ export async function updateHandler(
     await db.add_webxdc(file, payload.app_id)
     await db.update({ ...appInfo[payload.app_id], state: AppState.Received })
     setAppInfo(payload.app_id, 'state', AppState.Received)
+    if (request !== undefined && 'Download' in request)
+      pending.delete(payload.request_id!)
   }
   else if (isDownloadResponseError(payload)) {
-    console.log('Problem downloading some webxdc')
+    console.log('Problem downloading some webxdc', payload.error)
     setAppInfo(payload.app_id, 'state', AppState.DownloadCancelled)
+    if (request !== undefined && 'Download' in request)
+      pending.delete(payload.request_id!)
+  }
+  else if (isUpdatesAvailableResponse(payload)) {
+    console.log('Updates available for', payload.app_ids)
+    // only cached apps can be updated, the others are downloaded in the newest version anyway
+    setAppInfo(produce((s) => {
+      for (const key of payload.app_ids) {
+        if (s[key] !== undefined && s[key].state !== AppState.Initial)
+          s[key].state = AppState.UpdateAvailable
+      }
+    }))
+  }
+  else if (isUpdatePolicyResponse(payload)) {
+    console.log('Update policy changed to', payload.policy)
+    setUpdatePolicy(payload.policy)
+  }
+  else if (isErrorResponse(payload)) {
+    console.log('Request failed', payload.error, request)
+    if (request !== undefined) {
+      if ('Download' in request)
+        setAppInfo(request.Download.app_id, 'state', AppState.DownloadCancelled)
+      pending.delete(payload.request_id!)
+    }
+    if (request === undefined || 'Update' in request)
+      setIsUpdating(false)
   }
   else if (isOutdatedResponse(payload)) {
-    console.log('Current version is outdated')
-    setUpdateNeeded(true)
+    console.log('Current version is outdated, critical:', payload.critical)
+    if (payload.critical)
+      setUpdateNeeded(true)
+  }
+  else if (isWelcomeResponse(payload)) {
+    console.log('Bot speaks protocol', payload.protocol_version, 'with features', payload.features)
+    if (payload.update?.critical)
+      setUpdateNeeded(true)
   }
   else if (isUpdateSentResponse(payload)) {
     console.log('Update received')
@@ -6,6 +6,8 @@ export enum AppState {
   DownloadCancelled,
   Received,
   Updating,
+  /** A newer version is offered instead of sent automatically, see `UpdatePolicy`. */
+  UpdateAvailable,
 }
 
 export interface AppInfoWithState extends AppInfo {
@@ -13,3 +15,6 @@ export interface AppInfoWithState extends AppInfo {
 }
 
 export type AppInfosById = Record<string, AppInfoWithState>
+
+/** Request or response with an optional ID, which the bot echoes in all responses to a request. */
+export type Envelope<T> = T & { request_id?: string }
@@ -7,6 +7,10 @@ export function isAppInfo(p: any): p is AppInfo {
 
 export type WebxdcOutdatedResponse = Extract<GeneralFrontendResponse, { type: 'Outdated' }>
 export type WebxdcUpdateSentResponse = Extract<GeneralFrontendResponse, { type: 'UpdateSent' }>
+export type WebxdcWelcomeResponse = Extract<GeneralFrontendResponse, { type: 'Welcome' }>
+
+/** Version of the protocol spoken with the bot, see `PROTOCOL_VERSION` in the bot. */
+export const PROTOCOL_VERSION = 2
 
 export function isOutdatedResponse(p: any): p is WebxdcOutdatedResponse {
   return p.type === 'Outdated'
@@ -15,3 +19,7 @@ export function isOutdatedResponse(p: any): p is WebxdcOutdatedResponse {
 export function isUpdateSendResponse(p: any): p is WebxdcUpdateSentResponse {
   return p.type === 'UpdateSent'
 }
+
+export function isWelcomeResponse(p: any): p is WebxdcWelcomeResponse {
+  return p.type === 'Welcome'
+}
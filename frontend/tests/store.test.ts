@@ -4,8 +4,9 @@ import type { SetStoreFunction } from 'solid-js/store'
 import { createStore } from 'solid-js/store'
 import { AppInfoDB } from '../src/db/store_db'
 import { AppState } from '../src/types'
+import type { UpdatePolicy } from '../src/bindings/UpdatePolicy'
 import type { AppInfoWithState, AppInfosById } from '../src/types'
-import type { DownloadResponseError, DownloadResponseOkay, UpdateResponse } from '../src/store-logic'
+import type { DownloadResponseError, DownloadResponseOkay, UpdatePolicyResponse, UpdateResponse, UpdatesAvailableResponse } from '../src/store-logic'
 import { to_app_infos_by_id, updateHandler } from '../src/store-logic'
 import type { WebxdcOutdatedResponse, WebxdcUpdateSentResponse } from '../src/utils'
 import 'fake-indexeddb/auto'
@@ -18,6 +19,7 @@ const general_handlers = {
   setlastUpdate: ((() => { }) as Setter<Date>),
   setUpdateNeeded: ((() => { }) as Setter<boolean>),
   setUpdateReceived: ((() => { }) as Setter<boolean>),
+  setUpdatePolicy: ((() => { }) as Setter<UpdatePolicy>),
 }
 
 describe('Store receiving updates', () => {
@@ -35,7 +37,7 @@ describe('Store receiving updates', () => {
     } as WebxdcOutdatedResponse
 
     const updateNeeded = vi.spyOn(handlers, 'setUpdateNeeded')
-    updateHandler(payload, handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    updateHandler(payload, new Map(), handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setUpdatePolicy)
 
     expect(updateNeeded).toHaveBeenCalledWith(true)
   })
@@ -52,7 +54,7 @@ describe('Store receiving updates', () => {
     } as WebxdcUpdateSentResponse
 
     const setUpdateReceived = vi.spyOn(handlers, 'setUpdateReceived')
-    updateHandler(payload, handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    updateHandler(payload, new Map(), handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setUpdatePolicy)
     expect(setUpdateReceived).toHaveBeenCalledWith(true)
   })
 
@@ -70,7 +72,7 @@ describe('Store receiving updates', () => {
     } as DownloadResponseError
 
     const setAppInfo = vi.spyOn(handlers, 'setAppInfo')
-    updateHandler(payload, handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    updateHandler(payload, new Map(), handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setUpdatePolicy)
     expect(setAppInfo).toHaveBeenCalledWith(payload.app_id, 'state', AppState.DownloadCancelled)
   })
 
@@ -93,7 +95,7 @@ describe('Store receiving updates', () => {
     }
 
     const setAppInfo = vi.spyOn(handlers, 'setAppInfo')
-    await updateHandler(payload, handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    await updateHandler(payload, new Map(), handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setUpdatePolicy)
     expect(await db.get_webxdc(payload.app_id)).matchSnapshot()
     expect(await db.get(payload.app_id)).toStrictEqual({ ...mock[0], state: AppState.Received })
     expect(setAppInfo).toHaveBeenCalledWith(payload.app_id, 'state', AppState.Received)
@@ -108,7 +110,7 @@ describe('Store receiving updates', () => {
       data: 'test',
     }
 
-    await updateHandler(payload, handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    await updateHandler(payload, new Map(), handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setUpdatePolicy)
     expect(setAppInfo).toHaveBeenCalledWith(payload.app_id, 'state', AppState.Received)
     expect(await db.get(payload.app_id)).toStrictEqual({ ...mock[4], state: AppState.Received })
     expect(await db.get_webxdc(payload.app_id)).toMatchSnapshot()
@@ -127,10 +129,14 @@ describe('Store receiving updates', () => {
       app_infos: mock,
       serial: 12,
       updating: [],
+      removed: [],
+      stats: {},
+      installed: [],
+      update_policy: 'Auto',
     } as UpdateResponse
 
     const setAppInfo = vi.spyOn(handlers, 'setAppInfo')
-    await updateHandler(payload, handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    await updateHandler(payload, new Map(), handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setUpdatePolicy)
 
     const initial_mock = mock.map(app_info => ({ ...app_info, state: AppState.Initial } as AppInfoWithState))
     expect(setAppInfo).toHaveBeenCalledWith(to_app_infos_by_id(initial_mock))
@@ -159,6 +165,10 @@ describe('Store receiving updates', () => {
       app_infos: mock,
       serial: 12,
       updating: [],
+      removed: [],
+      stats: {},
+      installed: [],
+      update_policy: 'Auto',
     } as UpdateResponse
 
     const updateMultiple = vi.spyOn(db, 'updateMultiple')
@@ -167,7 +177,7 @@ describe('Store receiving updates', () => {
     const setIsUpdating = vi.spyOn(handlers, 'setIsUpdating')
     const setlastUpdate = vi.spyOn(handlers, 'setlastUpdate')
 
-    await updateHandler(payload, handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    await updateHandler(payload, new Map(), handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setUpdatePolicy)
 
     // Tests:
     // - Appinfo with state !== Initial are then in 'Updating'
@@ -185,9 +195,13 @@ describe('Store receiving updates', () => {
       app_infos: mock,
       serial: 12,
       updating: ['15'],
+      removed: [],
+      stats: {},
+      installed: [],
+      update_policy: 'Auto',
     } as UpdateResponse
 
-    await updateHandler(payload, handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    await updateHandler(payload, new Map(), handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setUpdatePolicy)
     expect(appInfo['15'].state).toBe(AppState.Updating)
 
     const download: DownloadResponseOkay = {
@@ -197,7 +211,92 @@ describe('Store receiving updates', () => {
       data: 'test',
     }
 
-    await updateHandler(download, handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived)
+    await updateHandler(download, new Map(), handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setUpdatePolicy)
     expect(appInfo['15'].state).toBe(AppState.Received)
   })
+
+  test('Handles stats of apps not in the update', async () => {
+    const db = new AppInfoDB('storetesting4')
+    const [appInfo, setAppInfo] = createStore(to_app_infos_by_id(mock.slice(0, 2)))
+    const handlers = {
+      ...general_handlers,
+      db,
+      appInfo,
+      setAppInfo,
+    }
+
+    const payload = {
+      type: 'Update',
+      app_infos: [],
+      serial: 12,
+      updating: [],
+      removed: [],
+      stats: { [mock[0].app_id]: { downloads: 3 }, unknown: { downloads: 1 } },
+      installed: [],
+      update_policy: 'Auto',
+    } as unknown as UpdateResponse
+
+    await updateHandler(payload, new Map(), handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setUpdatePolicy)
+    expect(appInfo[mock[0].app_id].downloads).toBe(3)
+    expect(appInfo.unknown).toBeUndefined()
+  })
+
+  test('Handles removed apps', async () => {
+    const db = new AppInfoDB('storetesting5')
+    const state = to_app_infos_by_id(mock.slice(0, 2))
+    await db.insertMultiple(Object.values(state))
+    const [appInfo, setAppInfo] = createStore(state)
+    const handlers = {
+      ...general_handlers,
+      db,
+      appInfo,
+      setAppInfo,
+    }
+
+    const payload = {
+      type: 'Update',
+      app_infos: [],
+      serial: 12,
+      updating: [],
+      removed: [mock[0].app_id],
+      stats: {},
+      installed: [],
+      update_policy: 'Auto',
+    } as unknown as UpdateResponse
+
+    await updateHandler(payload, new Map(), handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setUpdatePolicy)
+    expect(appInfo[mock[0].app_id]).toBeUndefined()
+    expect(await db.get(mock[0].app_id)).toBeUndefined()
+    expect(appInfo[mock[1].app_id]).toBeDefined()
+  })
+
+  test('Handles available updates and the update policy', async () => {
+    const db = new AppInfoDB('storetesting6')
+    const [appInfo, setAppInfo] = createStore(to_app_infos_by_id(mock.slice(0, 4)))
+    const handlers = {
+      ...general_handlers,
+      db,
+      appInfo,
+      setAppInfo,
+    }
+
+    const payload: UpdatesAvailableResponse = {
+      type: 'UpdatesAvailable',
+      app_ids: [mock[0].app_id, mock[3].app_id],
+    }
+
+    await updateHandler(payload, new Map(), handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setUpdatePolicy)
+    // apps which are not cached are left alone
+    expect(appInfo[mock[0].app_id].state).toBe(AppState.Initial)
+    expect(appInfo[mock[3].app_id].state).toBe(AppState.UpdateAvailable)
+
+    const policy: UpdatePolicyResponse = {
+      type: 'UpdatePolicy',
+      policy: 'Ask',
+    }
+
+    const setUpdatePolicy = vi.spyOn(handlers, 'setUpdatePolicy')
+    await updateHandler(policy, new Map(), handlers.db, handlers.appInfo, handlers.setAppInfo, handlers.setlastUpdateSerial, handlers.setIsUpdating, handlers.setlastUpdate, handlers.setUpdateNeeded, handlers.setUpdateReceived, handlers.setUpdatePolicy)
+    expect(setUpdatePolicy).toHaveBeenCalledWith('Ask')
+  })
 })
@@ -0,0 +1,14 @@
+-- Per-contact settings and localized app metadata
+
+CREATE TABLE IF NOT EXISTS contact_settings (
+    contact_id INTEGER PRIMARY KEY NOT NULL,
+    locale TEXT
+);
+
+CREATE TABLE IF NOT EXISTS app_info_translations (
+    app_info_id INTEGER NOT NULL REFERENCES app_infos (id),
+    locale TEXT NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT NOT NULL,
+    PRIMARY KEY (app_info_id, locale)
+);
@@ -0,0 +1,11 @@
+-- Update notification subscriptions
+
+CREATE TABLE IF NOT EXISTS subscriptions (
+    contact_id INTEGER NOT NULL,
+    app_id TEXT NOT NULL,
+    PRIMARY KEY (contact_id, app_id)
+);
+
+-- Serial up to which subscribers have been notified about updates
+ALTER TABLE config ADD COLUMN notified_serial INTEGER NOT NULL DEFAULT 0;
+UPDATE config SET notified_serial = serial;
@@ -0,0 +1,14 @@
+-- Download statistics
+
+CREATE TABLE IF NOT EXISTS downloads (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    app_id TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    contact_hash TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS downloads_app_id ON downloads (app_id);
+
+-- Salt used to anonymise contacts in the download statistics
+ALTER TABLE config ADD COLUMN stats_salt TEXT NOT NULL DEFAULT '';
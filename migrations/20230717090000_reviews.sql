@@ -0,0 +1,14 @@
+-- User ratings and reviews, one per contact and app version
+
+CREATE TABLE IF NOT EXISTS reviews (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    app_id TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    contact_id INTEGER NOT NULL,
+    stars INTEGER NOT NULL,
+    comment TEXT,
+    timestamp INTEGER NOT NULL,
+    UNIQUE (app_id, version, contact_id)
+);
+
+CREATE INDEX IF NOT EXISTS reviews_app_id ON reviews (app_id);
@@ -0,0 +1,11 @@
+-- Reports of broken or malicious apps for the genesis members
+
+CREATE TABLE IF NOT EXISTS reports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    app_id TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    contact_id INTEGER NOT NULL,
+    reason TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    resolved INTEGER NOT NULL DEFAULT 0
+);
@@ -0,0 +1,6 @@
+-- Store blob paths relative to the blob root instead of absolute paths.
+-- All imported xdcs live directly in the blob root, so only the file name is kept.
+
+UPDATE app_infos
+SET xdc_blob_path = replace(xdc_blob_path, rtrim(xdc_blob_path, replace(xdc_blob_path, '/', '')), '')
+WHERE xdc_blob_path LIKE '/%';
@@ -0,0 +1,8 @@
+-- Apps hidden from the store by an admin, all versions of a hidden app are excluded
+
+CREATE TABLE IF NOT EXISTS hidden_apps (
+    app_id TEXT PRIMARY KEY NOT NULL,
+    timestamp INTEGER NOT NULL,
+    -- Serial at which the app was hidden, so frontends which synced it before can remove it
+    serial INTEGER NOT NULL DEFAULT 0
+);
@@ -0,0 +1,17 @@
+-- Outgoing work like downloads and notifications, kept until it succeeded
+
+CREATE TABLE IF NOT EXISTS jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,
+    -- JSON encoded job
+    payload TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- unix timestamp of the next attempt
+    next_attempt INTEGER NOT NULL,
+    last_error TEXT,
+    created INTEGER NOT NULL,
+    -- set once the job failed too often
+    dead INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS jobs_next_attempt ON jobs (dead, next_attempt);
@@ -0,0 +1,3 @@
+-- Protocol version announced by a store.xdc in its handshake, NULL if it never sent one
+
+ALTER TABLE webxdc_versions ADD COLUMN protocol_version INTEGER;
@@ -0,0 +1,12 @@
+-- Apps a contact has installed, reported by the store xdc or recorded on download.
+-- They are stored per device, so devices with older copies don't overwrite newer ones.
+
+CREATE TABLE IF NOT EXISTS installed_apps (
+    contact_id INTEGER NOT NULL,
+    -- Device reported by the store xdc, '' if unknown.
+    device TEXT NOT NULL DEFAULT '',
+    app_id TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    PRIMARY KEY (contact_id, device, app_id)
+);
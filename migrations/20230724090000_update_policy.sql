@@ -0,0 +1,3 @@
+-- How updates of installed apps are delivered to a contact, NULL means automatically
+
+ALTER TABLE contact_settings ADD COLUMN update_policy TEXT;
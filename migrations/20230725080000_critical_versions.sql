@@ -0,0 +1,21 @@
+-- App versions which fix critical, e.g. security, problems of older versions
+
+ALTER TABLE app_infos ADD COLUMN critical BOOLEAN NOT NULL DEFAULT 0;
+
+-- Versions of each app users were last notified about.
+-- Apps get new serials without new versions, e.g. when marked critical, this prevents repeated notifications.
+
+CREATE TABLE IF NOT EXISTS notified_versions (
+    app_id TEXT PRIMARY KEY NOT NULL,
+    -- Version subscribers were notified about.
+    version INTEGER NOT NULL,
+    -- Newest critical version users with older versions were notified about, 0 if none.
+    critical_version INTEGER NOT NULL
+);
+
+-- No version was marked critical before
+INSERT INTO notified_versions (app_id, version, critical_version)
+SELECT app_id, MAX(version), 0
+FROM app_infos
+WHERE serial <= (SELECT notified_serial FROM config)
+GROUP BY app_id;
@@ -13,21 +13,32 @@ use log::{debug, error, info, trace, warn};
 use qrcode_generator::QrCodeEcc;
 use serde::{Deserialize, Serialize};
 use sqlx::{pool::PoolConnection, Sqlite, SqlitePool};
-use std::{fs, sync::Arc};
+use std::{fs, sync::Arc, time::Duration};
+use tokio::{
+    sync::{watch, Notify},
+    task::JoinHandle,
+};
 
 use crate::{
+    blob_store::{self, BlobStore},
     db::{self, MIGRATOR},
-    messages::store_message,
-    project_dirs,
+    http_api,
+    jobs::job_loop,
+    metrics::{self, Metrics},
+    notifications::notification_loop,
+    rate_limit::RateLimiter,
     request_handlers::{
-        genesis, store, ChatType, GeneralFrontendRequest, GeneralFrontendResponse,
-        WebxdcStatusUpdate,
+        commands, genesis, get_request_id, is_bot_update, store, ChatType, Envelope,
+        GeneralFrontendRequest, GeneralFrontendResponse, StoreXdcUpdate, WebxdcStatusUpdate,
+        FEATURES, LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION,
     },
+    settings::Settings,
     utils::{
-        configure_from_env, read_webxdc_versions, send_newest_updates, send_update_payload_only,
-        send_webxdc, unpack_assets, Webxdc, WebxdcVersions,
+        configure_account, read_webxdc_versions, send_response, send_store_xdc, unpack_assets,
+        WebxdcVersions,
     },
-    GENESIS_QR, INVITE_QR, VERSION,
+    workers::WorkerPool,
+    GENESIS_QR, INVITE_QR,
 };
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Default)]
@@ -43,30 +54,39 @@ pub struct BotConfig {
 pub struct State {
     pub db: SqlitePool,
     pub config: BotConfig,
+    pub settings: Settings,
     pub webxdc_versions: WebxdcVersions,
+    pub rate_limiter: RateLimiter,
+    pub blob_store: Box<dyn BlobStore>,
+    pub metrics: Metrics,
+    /// Notified when a job was added, see [crate::jobs::enqueue].
+    pub new_jobs: Notify,
 }
 
 /// Github Bot
 pub struct Bot {
     dc_ctx: Context,
     state: Arc<State>,
+    /// Set to `true` to make the event and notification loops stop after their current work.
+    shutdown: watch::Sender<bool>,
+    /// Tasks which are drained on [Bot::stop].
+    tasks: Vec<JoinHandle<()>>,
 }
 
 impl Bot {
     /// Creates a new instance of the bot.
     /// Handles the configuration for dc and the bot itself.
-    pub async fn new() -> Result<Self> {
+    pub async fn new(settings: Settings) -> Result<Self> {
+        let data_dir = settings.data_dir.clone();
         if std::env::var("XDCSTORE_KEEP_ASSETS")
             .unwrap_or_default()
             .is_empty()
         {
-            unpack_assets().context("failed to unpack assets")?;
+            unpack_assets(&data_dir).context("failed to unpack assets")?;
         }
 
-        let dirs = project_dirs()?;
-
-        std::fs::create_dir_all(dirs.config_dir())?;
-        let deltachat_db_file = dirs.config_dir().to_path_buf().join("deltachat.db");
+        std::fs::create_dir_all(&data_dir)?;
+        let deltachat_db_file = data_dir.join("deltachat.db");
         let context = Context::new(
             deltachat_db_file.as_path(),
             1,
@@ -78,11 +98,11 @@ impl Bot {
 
         if !context.get_config_bool(Config::Configured).await? {
             info!("DC: Start configuring...");
-            configure_from_env(&context).await?;
+            configure_account(&context, &settings.account).await?;
             info!("DC: Configuration done");
         }
 
-        let bot_db_file = dirs.config_dir().to_path_buf().join("bot.db");
+        let bot_db_file = data_dir.join("bot.db");
         if !bot_db_file.exists() {
             fs::write(&bot_db_file, "")?;
         }
@@ -104,11 +124,11 @@ impl Bot {
                 db::set_chat_type(conn, config.genesis_group, ChatType::Genesis).await?;
 
                 // save qr codes to disk
-                let dest_path = dirs.config_dir().to_path_buf().join(GENESIS_QR);
+                let dest_path = data_dir.join(GENESIS_QR);
                 qrcode_generator::to_png_to_file(
                     &config.genesis_qr,
                     QrCodeEcc::Low,
-                    1024,
+                    settings.qr_size,
                     &dest_path,
                 )
                 .context("failed to generate genesis QR at {dest_path}")?;
@@ -117,11 +137,11 @@ impl Bot {
                     dest_path.display()
                 );
 
-                let dest_path = dirs.config_dir().to_path_buf().join(INVITE_QR);
+                let dest_path = data_dir.join(INVITE_QR);
                 qrcode_generator::to_png_to_file(
                     &config.invite_qr,
                     QrCodeEcc::Low,
-                    1024,
+                    settings.qr_size,
                     &dest_path,
                 )
                 .context("failed to generate invite QR at {dest_path}")?;
@@ -130,16 +150,23 @@ impl Bot {
             }
         };
 
-        let webxdc_versions = read_webxdc_versions().await.map_err(|e| {
+        let webxdc_versions = read_webxdc_versions(&data_dir).await.map_err(|e| {
             anyhow::anyhow!("Problem while parsing one of the store `manifests.toml`s: \n {e}")
         })?;
         info!("Loaded webxdc versions: {:?}", webxdc_versions);
 
         Ok(Self {
             dc_ctx: context,
+            shutdown: watch::channel(false).0,
+            tasks: Vec::new(),
             state: Arc::new(State {
                 db,
                 config,
+                rate_limiter: RateLimiter::new(settings.limits.requests_per_minute),
+                blob_store: blob_store::from_settings(&settings)?,
+                metrics: Metrics::new(),
+                new_jobs: Notify::new(),
+                settings,
                 webxdc_versions,
             }),
         })
@@ -169,18 +196,110 @@ impl Bot {
         let events_emitter = self.dc_ctx.get_event_emitter();
         let ctx = self.dc_ctx.clone();
         let state = self.state.clone();
-
-        tokio::spawn(async move {
-            while let Some(event) = events_emitter.recv().await {
-                if let Err(e) = Self::dc_event_handler(&ctx, state.clone(), event.typ).await {
-                    warn!("{}", e)
+        let mut shutdown = self.shutdown.subscribe();
+        let workers = &self.state.settings.workers;
+        let (pool, handles) =
+            WorkerPool::spawn(&self.dc_ctx, &self.state, workers.count, workers.queue_size);
+        self.tasks.extend(handles);
+
+        self.tasks.push(tokio::spawn(async move {
+            loop {
+                // an event being handled is finished before checking for shutdown again
+                let event = tokio::select! {
+                    _ = shutdown.changed() => break,
+                    event = events_emitter.recv() => event,
+                };
+                let Some(event) = event else {
+                    break;
+                };
+                match Self::event_chat(&ctx, &event.typ).await {
+                    Ok(Some(chat_id)) => pool.dispatch(chat_id, event.typ).await,
+                    Ok(None) => Self::handle_event(&ctx, &state, event.typ).await,
+                    Err(e) => {
+                        state.metrics.record_error();
+                        warn!("Failed to get chat of event: {e:#}");
+                    }
                 }
             }
-        });
+            // the workers finish their queued events and stop once the pool is dropped
+            drop(pool);
+            info!("Stopped receiving events");
+        }));
+        self.tasks.push(tokio::spawn(job_loop(
+            self.dc_ctx.clone(),
+            self.state.clone(),
+            self.shutdown.subscribe(),
+        )));
+        self.tasks.push(tokio::spawn(notification_loop(
+            self.dc_ctx.clone(),
+            self.state.clone(),
+            self.shutdown.subscribe(),
+        )));
+        if let Some(addr) = self.state.settings.metrics.listen {
+            let state = self.state.clone();
+            let shutdown = self.shutdown.subscribe();
+            self.tasks.push(tokio::spawn(async move {
+                if let Err(e) = metrics::serve(state, addr, shutdown).await {
+                    error!("Metrics server stopped: {e:#}");
+                }
+            }));
+        }
+        if let Some(addr) = self.state.settings.http.listen {
+            let state = self.state.clone();
+            let shutdown = self.shutdown.subscribe();
+            self.tasks.push(tokio::spawn(async move {
+                if let Err(e) = http_api::serve(state, addr, shutdown).await {
+                    error!("HTTP API stopped: {e:#}");
+                }
+            }));
+        }
         self.dc_ctx.start_io().await;
+        self.state
+            .metrics
+            .set_connectivity(self.dc_ctx.get_connectivity().await as u32);
         info!("successfully started bot! 🥳");
     }
 
+    /// Stops the bot: no new events are handled, running handlers get up to `timeout` to finish,
+    /// then the IO of the context is stopped and the database closed.
+    pub async fn stop(self, timeout: Duration) {
+        info!("Shutting down...");
+        self.shutdown.send_replace(true);
+        let tasks = futures::future::join_all(self.tasks);
+        if tokio::time::timeout(timeout, tasks).await.is_err() {
+            warn!(
+                "Requests still running after {}s, stopping anyway",
+                timeout.as_secs()
+            );
+        }
+        self.dc_ctx.stop_io().await;
+        self.state.db.close().await;
+        info!("Shutdown complete");
+    }
+
+    /// Returns the chat of events handled by the workers, other events are handled directly.
+    async fn event_chat(context: &Context, event: &EventType) -> Result<Option<ChatId>> {
+        Ok(match event {
+            EventType::IncomingMsg { chat_id, .. } | EventType::ChatModified(chat_id) => {
+                Some(*chat_id)
+            }
+            EventType::WebxdcStatusUpdate { msg_id, .. } => {
+                Some(Message::load_from_db(context, *msg_id).await?.get_chat_id())
+            }
+            _ => None,
+        })
+    }
+
+    /// Handles an event and records failures in the metrics.
+    pub(crate) async fn handle_event(context: &Context, state: &Arc<State>, event: EventType) {
+        let result = Self::dc_event_handler(context, state.clone(), event).await;
+        state.metrics.record_event();
+        if let Err(e) = result {
+            state.metrics.record_error();
+            warn!("{}", e)
+        }
+    }
+
     /// Handle dc-events.
     async fn dc_event_handler(
         context: &Context,
@@ -194,7 +313,11 @@ impl Bot {
             EventType::Info(msg) => trace!("DC: {msg}"),
             EventType::Warning(msg) => warn!("DC: {msg}"),
             EventType::Error(msg) => error!("DC: {msg}"),
-            EventType::ConnectivityChanged => trace!("DC: ConnectivityChanged"),
+            EventType::ConnectivityChanged => {
+                let connectivity = context.get_connectivity().await as u32;
+                state.metrics.set_connectivity(connectivity);
+                info!("DC: Connectivity changed to {connectivity}");
+            }
             EventType::IncomingMsg { chat_id, msg_id } => {
                 Self::handle_dc_message(context, state, chat_id, msg_id).await?
             }
@@ -224,22 +347,7 @@ impl Bot {
                         "Chat {chat_id} is not in the database, adding it as chat with type store"
                     );
                         db::set_chat_type(conn, chat_id, ChatType::Store).await?;
-                        let msg = send_webxdc(
-                            context,
-                            &state,
-                            chat_id,
-                            Webxdc::Store,
-                            Some(store_message()),
-                        )
-                        .await?;
-                        send_newest_updates(
-                            context,
-                            msg,
-                            &mut *state.db.acquire().await?,
-                            0,
-                            vec![],
-                        )
-                        .await?;
+                        send_store_xdc(context, &state, chat_id).await?;
                     }
                 }
             }
@@ -258,34 +366,31 @@ impl Bot {
         msg_id: MsgId,
     ) -> Result<()> {
         let msg = Message::load_from_db(context, msg_id).await?;
+
+        let chat_type = match db::get_chat_type(&mut *state.db.acquire().await?, chat_id).await {
+            Ok(chat_type) => chat_type,
+            Err(sqlx::Error::RowNotFound) => {
+                info!("creating new 1:1 chat with type Store");
+                db::set_chat_type(&mut *state.db.acquire().await?, chat_id, ChatType::Store)
+                    .await?;
+                ChatType::Store
+            }
+            Err(e) => {
+                warn!("Problem while retrieving [ChatType]: {}", e);
+                return Ok(());
+            }
+        };
+
         if let Some(text) = msg.get_text() {
-            if text == "/version" {
-                chat::send_text_msg(context, chat_id, VERSION.to_string()).await?;
+            if commands::handle_command(context, &state, chat_id, chat_type, &text).await? {
                 return Ok(());
             }
         }
 
-        match db::get_chat_type(&mut *state.db.acquire().await?, chat_id).await {
-            Ok(chat_type) => {
-                info!("Handling message with type <{chat_type:?}>");
-                match chat_type {
-                    ChatType::Store => {
-                        store::handle_message(context, state, chat_id).await?;
-                    }
-                    ChatType::Genesis => {
-                        genesis::handle_message(context, state, chat_id, msg_id).await?
-                    }
-                }
-            }
-            Err(e) => match e {
-                sqlx::Error::RowNotFound => {
-                    info!("creating new 1:1 chat with type Store");
-                    db::set_chat_type(&mut *state.db.acquire().await?, chat_id, ChatType::Store)
-                        .await?;
-                    store::handle_message(context, state, chat_id).await?;
-                }
-                _ => warn!("Problem while retrieving [ChatType]: {}", e),
-            },
+        info!("Handling message with type <{chat_type:?}>");
+        match chat_type {
+            ChatType::Store => store::handle_message(context, state, chat_id).await?,
+            ChatType::Genesis => genesis::handle_message(context, state, chat_id, msg_id).await?,
         }
         Ok(())
     }
@@ -297,54 +402,67 @@ impl Bot {
         msg_id: MsgId,
         update: String,
     ) -> anyhow::Result<()> {
+        if is_bot_update(&update) {
+            trace!("Ignoring self-sent update of {msg_id}");
+            return Ok(());
+        }
+
         let msg = Message::load_from_db(context, msg_id).await?;
         let chat_id = msg.get_chat_id();
         let conn = &mut *state.db.acquire().await?;
         let chat_type = db::get_chat_type(conn, chat_id).await?;
         let (webxdc, version) = db::get_webxdc_version(conn, msg.get_id()).await?;
 
+        let newest_version = state.webxdc_versions.get(webxdc);
+
         if let Ok(request) =
-            serde_json::from_str::<WebxdcStatusUpdate<GeneralFrontendRequest>>(&update)
+            serde_json::from_str::<WebxdcStatusUpdate<Envelope<GeneralFrontendRequest>>>(&update)
         {
-            match request.payload {
+            let request_id = request.payload.request_id.as_deref();
+            let response = match request.payload.content {
                 GeneralFrontendRequest::UpdateWebxdc => {
-                    let msg = send_webxdc(context, &state, chat_id, webxdc, Some(store_message()))
-                        .await?;
-                    send_newest_updates(context, msg, &mut *state.db.acquire().await?, 0, vec![])
-                        .await?;
-                    send_update_payload_only(context, msg_id, GeneralFrontendResponse::UpdateSent)
-                        .await?;
-                    return Ok(());
+                    send_store_xdc(context, &state, chat_id).await?;
+                    GeneralFrontendResponse::UpdateSent
                 }
-            }
+                GeneralFrontendRequest::Hello {
+                    protocol_version,
+                    capabilities,
+                } => {
+                    info!("Handshake in chat {chat_id}: protocol {protocol_version}, {capabilities:?}");
+                    db::set_protocol_version(conn, msg_id, protocol_version).await?;
+                    GeneralFrontendResponse::Welcome {
+                        protocol_version: PROTOCOL_VERSION,
+                        features: FEATURES.to_vec(),
+                        update: StoreXdcUpdate::check(version, newest_version, protocol_version),
+                    }
+                }
+            };
+            send_response(context, msg_id, request_id, response).await?;
+            return Ok(());
         };
 
-        if version < state.webxdc_versions.get(webxdc) {
-            info!("Webxdc version mismatch, updating");
-
-            if serde_json::from_str::<WebxdcStatusUpdate<GeneralFrontendResponse>>(&update).is_ok()
-            {
-                return Ok(());
-            }
-
-            // Only try to upgrade version, if the webxdc event is _not_ an update response already
-            if serde_json::from_str::<WebxdcStatusUpdate<GeneralFrontendRequest>>(&update).is_err()
+        // Frontends with handshake learn about updates from the `Welcome` response.
+        // Older ones are told with every request, which is still handled in a degraded mode.
+        if db::get_protocol_version(conn, msg_id).await?.is_none() {
+            if let Some(store_update) =
+                StoreXdcUpdate::check(version, newest_version, LEGACY_PROTOCOL_VERSION)
             {
-                send_update_payload_only(
+                info!("Webxdc version mismatch, asking to update");
+                send_response(
                     context,
                     msg_id,
+                    get_request_id(&update).as_deref(),
                     GeneralFrontendResponse::Outdated {
-                        version: state.webxdc_versions.get(webxdc),
-                        critical: true,
+                        version: store_update.version,
+                        critical: store_update.critical,
                     },
                 )
                 .await?;
-            };
-            return Ok(());
+            }
         }
 
         if chat_type == ChatType::Store {
-            store::handle_status_update(context, state, msg_id, update).await?
+            store::handle_status_update(context, state, chat_id, msg_id, update).await?
         }
 
         Ok(())
@@ -353,4 +471,8 @@ impl Bot {
     pub async fn get_db_connection(&self) -> sqlx::Result<PoolConnection<Sqlite>> {
         self.state.db.acquire().await
     }
+
+    pub fn blob_store(&self) -> &dyn BlobStore {
+        self.state.blob_store.as_ref()
+    }
 }
@@ -0,0 +1,205 @@
+//! Portable store bundles for moving the app catalog between bot instances.
+//!
+//! A bundle is a zip archive with an `index.json` describing every app version
+//! and the xdc of each version stored under `xdcs/`.
+//! User data like subscriptions, reviews and download statistics is not part of a bundle.
+
+use anyhow::{bail, Context as _, Result};
+use async_zip::{
+    tokio::{read::fs::ZipFileReader, write::ZipFileWriter},
+    Compression, ZipEntryBuilder,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+
+use crate::{
+    blob_store::BlobStore,
+    db,
+    request_handlers::{AppInfo, AppTranslation},
+    utils::{is_valid_app_id, maybe_upgrade_xdc, read_string, read_vec, sha256_hex, AddType},
+};
+
+/// Name of the index inside the bundle.
+const INDEX_FILE: &str = "index.json";
+/// Version of the bundle layout, increased on incompatible changes.
+const BUNDLE_FORMAT: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct BundleIndex {
+    format: u32,
+    /// All app versions in the order they were added to the store.
+    apps: Vec<BundleApp>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BundleApp {
+    #[serde(flatten)]
+    pub app_info: AppInfo,
+    pub submitter_uri: Option<String>,
+    #[serde(default)]
+    pub translations: Vec<AppTranslation>,
+    /// Path of the xdc inside the bundle.
+    pub blob: String,
+    /// Hex encoded SHA-256 of the xdc.
+    pub sha256: String,
+}
+
+/// An opened bundle.
+pub struct Bundle {
+    reader: ZipFileReader,
+    pub apps: Vec<BundleApp>,
+}
+
+impl Bundle {
+    pub async fn open(archive: &Path) -> Result<Self> {
+        let reader = ZipFileReader::new(archive)
+            .await
+            .with_context(|| format!("Failed to open {}", archive.display()))?;
+        let index: BundleIndex =
+            serde_json::from_str(&read_string(&reader, entry_index(&reader, INDEX_FILE)?).await?)
+                .context("Failed to parse bundle index")?;
+        if index.format != BUNDLE_FORMAT {
+            bail!("Unsupported bundle format {}", index.format);
+        }
+        Ok(Self {
+            reader,
+            apps: index.apps,
+        })
+    }
+
+    /// Reads the xdc of an app version and verifies its hash.
+    /// Fails without reading it if the xdc is larger than `max_size` bytes.
+    pub async fn read_blob(&self, app: &BundleApp, max_size: u64) -> Result<Vec<u8>> {
+        let index = entry_index(&self.reader, &app.blob)?;
+        let size = self
+            .reader
+            .file()
+            .entries()
+            .get(index)
+            .context("Invalid entry index")?
+            .entry()
+            .uncompressed_size();
+        if size > max_size {
+            bail!(
+                "{} is {size} bytes, which exceeds the limit of {max_size} bytes",
+                app.blob
+            );
+        }
+        let data = read_vec(&self.reader, index).await?;
+        if sha256_hex(&data) != app.sha256 {
+            bail!("Hash mismatch for {}", app.blob);
+        }
+        Ok(data)
+    }
+}
+
+/// Result of importing a bundle.
+pub struct ImportSummary {
+    pub added: usize,
+    /// App versions which already existed in the store.
+    pub skipped: usize,
+}
+
+/// Writes all app versions with their xdcs from the blob store to a bundle at `archive`.
+/// Returns the number of exported app versions.
+pub async fn export_store(
+    conn: &mut SqliteConnection,
+    archive: &Path,
+    blob_store: &dyn BlobStore,
+) -> Result<usize> {
+    let mut app_infos = db::get_app_infos(conn).await?;
+    db::load_translations(conn, &mut app_infos).await?;
+
+    let file = File::create(archive)
+        .await
+        .with_context(|| format!("Failed to create {}", archive.display()))?;
+    let mut writer = ZipFileWriter::with_tokio(file);
+
+    let mut apps = Vec::with_capacity(app_infos.len());
+    for mut app_info in app_infos {
+        let blob = format!("xdcs/{}-{}.xdc", app_info.app_id, app_info.version);
+        let data = blob_store.get(app_info.blob_key()?).await?;
+        // xdcs are zip files already, compressing them again gains nothing
+        writer
+            .write_entry_whole(
+                ZipEntryBuilder::new(blob.clone().into(), Compression::Stored),
+                &data,
+            )
+            .await?;
+
+        apps.push(BundleApp {
+            sha256: sha256_hex(&data),
+            submitter_uri: app_info.submitter_uri.take(),
+            translations: std::mem::take(&mut app_info.translations),
+            app_info,
+            blob,
+        });
+    }
+
+    let count = apps.len();
+    let index = serde_json::to_vec_pretty(&BundleIndex {
+        format: BUNDLE_FORMAT,
+        apps,
+    })?;
+    writer
+        .write_entry_whole(
+            ZipEntryBuilder::new(INDEX_FILE.to_string().into(), Compression::Deflate),
+            &index,
+        )
+        .await?;
+    writer.close().await?;
+    Ok(count)
+}
+
+/// Adds all app versions of the bundle at `archive` which don't exist in the store yet.
+/// The versions are added like imported xdcs, see [crate::import::import_one],
+/// with their hash as blob key, so xdcs of different apps can't replace each other.
+pub async fn import_store(
+    conn: &mut SqliteConnection,
+    archive: &Path,
+    blob_store: &dyn BlobStore,
+    max_size: u64,
+) -> Result<ImportSummary> {
+    let bundle = Bundle::open(archive).await?;
+    let mut summary = ImportSummary {
+        added: 0,
+        skipped: 0,
+    };
+    for app in &bundle.apps {
+        let app_id = &app.app_info.app_id;
+        if !is_valid_app_id(app_id) {
+            bail!("Invalid app_id {app_id:?} in bundle");
+        }
+        if db::app_version_exists(conn, app_id, app.app_info.version).await? {
+            summary.skipped += 1;
+            continue;
+        }
+
+        let key = format!("{}.xdc", app.sha256);
+        blob_store
+            .put(&key, bundle.read_blob(app, max_size).await?)
+            .await?;
+
+        let mut app_info = app.app_info.clone();
+        app_info.xdc_blob_path = PathBuf::from(key);
+        app_info.submitter_uri = app.submitter_uri.clone();
+        app_info.translations = app.translations.clone();
+        match maybe_upgrade_xdc(&mut app_info, conn).await? {
+            AddType::Added | AddType::Updated => summary.added += 1,
+            AddType::Ignored => summary.skipped += 1,
+        }
+    }
+    Ok(summary)
+}
+
+/// Returns the index of the entry with the given name.
+fn entry_index(reader: &ZipFileReader, name: &str) -> Result<usize> {
+    reader
+        .file()
+        .entries()
+        .iter()
+        .position(|entry| entry.entry().filename().as_str().ok() == Some(name))
+        .with_context(|| format!("Bundle has no entry {name}"))
+}
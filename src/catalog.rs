@@ -0,0 +1,221 @@
+//! Static catalog of the store, readable without Delta Chat.
+//!
+//! The catalog is a `catalog.json` listing the newest version of every app
+//! with a link to its xdc and the xdc's hash, so other stores can mirror it.
+//! It is exported together with an `index.html` for browsers.
+
+use anyhow::{Context as _, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::path::Path;
+
+use crate::{
+    blob_store::BlobStore,
+    db,
+    request_handlers::AppInfo,
+    utils::{format_date, is_valid_app_id, sha256_hex},
+};
+
+/// Name of the catalog index.
+pub const CATALOG_FILE: &str = "catalog.json";
+/// Version of the catalog layout, increased on incompatible changes.
+pub const CATALOG_FORMAT: u32 = 1;
+/// Name of the static page listing the apps.
+const HTML_FILE: &str = "index.html";
+/// Directory of the xdcs next to the catalog.
+const XDC_DIR: &str = "xdcs";
+
+#[derive(Serialize, Deserialize)]
+pub struct CatalogIndex {
+    pub format: u32,
+    pub apps: Vec<CatalogApp>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CatalogApp {
+    #[serde(flatten)]
+    pub app_info: AppInfo,
+    /// URL of the xdc, relative URLs are resolved against the catalog location.
+    pub url: String,
+    /// Hex encoded SHA-256 of the xdc.
+    pub sha256: String,
+}
+
+/// Writes the newest version of every app with its xdc to `dir`, as `catalog.json`
+/// and as a static `index.html`. Returns the number of exported apps.
+pub async fn export_catalog(
+    conn: &mut SqliteConnection,
+    dir: &Path,
+    blob_store: &dyn BlobStore,
+) -> Result<usize> {
+    let mut app_infos = db::get_active_app_infos_since(conn, 0).await?;
+    app_infos.sort_by_key(|app_info| app_info.name.to_lowercase());
+
+    let xdc_dir = dir.join(XDC_DIR);
+    tokio::fs::create_dir_all(&xdc_dir)
+        .await
+        .with_context(|| format!("Failed to create {}", xdc_dir.display()))?;
+
+    let mut apps = Vec::with_capacity(app_infos.len());
+    for app_info in app_infos {
+        if !is_valid_app_id(&app_info.app_id) {
+            warn!(
+                "Not exporting app with invalid app_id {:?}",
+                app_info.app_id
+            );
+            continue;
+        }
+        let file_name = format!("{}-{}.xdc", app_info.app_id, app_info.version);
+        let data = blob_store.get(app_info.blob_key()?).await?;
+        tokio::fs::write(xdc_dir.join(&file_name), &data).await?;
+        apps.push(CatalogApp {
+            url: format!("{XDC_DIR}/{file_name}"),
+            sha256: sha256_hex(&data),
+            app_info,
+        });
+    }
+
+    tokio::fs::write(dir.join(HTML_FILE), render_html(&apps)).await?;
+    let count = apps.len();
+    let index = serde_json::to_vec_pretty(&CatalogIndex {
+        format: CATALOG_FORMAT,
+        apps,
+    })?;
+    tokio::fs::write(dir.join(CATALOG_FILE), index).await?;
+    Ok(count)
+}
+
+/// Renders a page listing all apps with their icon, description and download link.
+fn render_html(apps: &[CatalogApp]) -> String {
+    let mut html = String::from(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Webxdc App Store</title>
+<style>
+body { font-family: sans-serif; max-width: 48em; margin: 0 auto; padding: 1em; }
+.app { display: flex; gap: 1em; margin-bottom: 1.5em; }
+.app img { width: 64px; height: 64px; border-radius: 12px; object-fit: cover; }
+.app h2 { margin: 0; font-size: 1.2em; }
+.app p { margin: 0.3em 0; }
+.meta { color: #666; font-size: 0.9em; }
+</style>
+</head>
+<body>
+<h1>Webxdc App Store</h1>
+"#,
+    );
+    for app in apps {
+        let info = &app.app_info;
+        let source = info
+            .source_code_url
+            .as_deref()
+            .filter(|url| is_web_url(url))
+            .map(|url| format!(r#" · <a href="{}">Source code</a>"#, escape(url)))
+            .unwrap_or_default();
+        html.push_str(&format!(
+            r#"<div class="app">
+<img src="data:{mime_type};base64,{image}" alt="">
+<div>
+<h2>{name}</h2>
+<p>{description}</p>
+<p class="meta">Version {version} · {date} · {size} KiB · <a href="{url}" download>Download</a>{source}</p>
+</div>
+</div>
+"#,
+            mime_type = image_mime_type(&info.image),
+            image = escape(&info.image),
+            name = escape(&info.name),
+            description = escape(&info.description),
+            version = info.version,
+            date = format_date(info.date),
+            size = info.size / 1024,
+            url = escape(&app.url),
+        ));
+    }
+    html.push_str("</body>\n</html>\n");
+    html
+}
+
+/// Returns whether a URL is safe to link, other schemes like `javascript:` could run scripts.
+fn is_web_url(url: &str) -> bool {
+    let url = url.trim_start().to_ascii_lowercase();
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+/// Returns the MIME type of a base64 encoded app icon, which is a PNG or JPEG.
+fn image_mime_type(image: &str) -> &'static str {
+    // base64 of the JPEG magic bytes FF D8 FF
+    if image.starts_with("/9j/") {
+        "image/jpeg"
+    } else {
+        "image/png"
+    }
+}
+
+/// Escapes text for use in HTML content and attribute values.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_html() {
+        let apps = vec![CatalogApp {
+            app_info: AppInfo {
+                app_id: "poll".to_string(),
+                version: 2,
+                date: 1689811200,
+                name: "Poll <beta>".to_string(),
+                description: "Ask \"anything\" & vote".to_string(),
+                size: 4096,
+                ..Default::default()
+            },
+            url: "xdcs/poll-2.xdc".to_string(),
+            sha256: String::new(),
+        }];
+        let html = render_html(&apps);
+        assert!(html.contains("<h2>Poll &lt;beta&gt;</h2>"));
+        assert!(html.contains("<p>Ask &quot;anything&quot; &amp; vote</p>"));
+        assert!(html.contains("Version 2 · 2023-07-20 · 4 KiB"));
+        assert!(html.contains(r#"<a href="xdcs/poll-2.xdc" download>"#));
+        assert!(!html.contains("Source code"));
+        assert!(html.contains("data:image/png;base64,"));
+    }
+
+    #[test]
+    fn test_render_html_links() {
+        let app = |source_code_url: &str, image: &str| CatalogApp {
+            app_info: AppInfo {
+                source_code_url: Some(source_code_url.to_string()),
+                image: image.to_string(),
+                ..Default::default()
+            },
+            url: String::new(),
+            sha256: String::new(),
+        };
+        let html = render_html(&[app("javascript:alert(1)", "/9j/4AAQ")]);
+        assert!(!html.contains("javascript:"));
+        assert!(html.contains("data:image/jpeg;base64,/9j/4AAQ"));
+
+        let html = render_html(&[app("https://example.org/poll", "")]);
+        assert!(html.contains(r#"<a href="https://example.org/poll">Source code</a>"#));
+    }
+}
@@ -1,8 +1,20 @@
 use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use crate::db::RecordId;
 
 #[derive(Parser, Debug)]
 #[command()]
 pub struct BotCli {
+    /// Path to the TOML config file.
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Directory for the databases, assets and imported xdcs of this store instance.
+    /// Overrides `data_dir` from the config file and `XDCSTORE_DATA_DIR`.
+    #[arg(long, global = true)]
+    pub data_dir: Option<PathBuf>,
+
     #[command(subcommand)]
     pub action: BotActions,
 }
@@ -15,9 +27,68 @@ pub enum BotActions {
     Import {
         /// Path from which files should be imported.
         path: String,
+        /// Mark the imported versions as critical updates,
+        /// users of older versions are notified by the running bot.
+        #[arg(long)]
+        critical: bool,
+    },
+    /// Mark an app version as critical update of older versions.
+    /// Users of older versions are notified by the running bot.
+    MarkCritical {
+        app_id: String,
+        version: u32,
+        /// Remove the mark instead.
+        #[arg(long)]
+        unset: bool,
     },
     /// Show the 1:1-invite and genesis-invite qr code.
     ShowQr,
     /// Show the bots version.
     Version,
+    /// Show download statistics per app.
+    Stats {
+        /// Number of days counted as recent.
+        #[arg(long, default_value_t = 30)]
+        days: i64,
+    },
+    /// Export all apps with their xdcs to a portable bundle.
+    ///
+    /// The bundle only contains app infos, translations and xdcs. Subscriptions, reviews,
+    /// reports, contact settings, download statistics and the bot account are not exported,
+    /// copy the whole data directory to move them.
+    ExportStore {
+        /// Path of the zip archive to create.
+        archive: PathBuf,
+    },
+    /// Import the apps of a bundle created with `export-store`.
+    ImportStore {
+        /// Path of the zip archive to import.
+        archive: PathBuf,
+    },
+    /// Write the newest version of every app to a static JSON/HTML catalog.
+    ExportCatalog {
+        /// Directory for `catalog.json`, `index.html` and the xdcs.
+        dir: PathBuf,
+    },
+    /// Mirror new app versions from another store.
+    Sync {
+        /// URL or path of a catalog, or path of a bundle created with `export-store`.
+        #[arg(long)]
+        from: String,
+        /// Only show which app versions would be added.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// List downloads and notifications which failed too often to be sent.
+    DeadJobs {
+        /// Schedule the dead job with this id again.
+        #[arg(long)]
+        retry: Option<RecordId>,
+    },
+    /// Show health and metrics of the running bot, requires `metrics.listen` to be set.
+    Status,
+    /// Check for app versions with missing xdcs and xdcs without app versions.
+    Fsck,
+    /// Validate the config file and print the resulting settings.
+    CheckConfig,
 }
@@ -5,6 +5,16 @@
 //! - chats (Stores information about the review and submit chats)
 //! - chat_to_chat_type (Acts as a map between ChatId and ChatType)
 //! - config (Where config is stored)
+//! - contact_settings (Per-contact user settings like the locale)
+//! - app_info_translations (Localized names and descriptions of app infos)
+//! - subscriptions (Apps a contact wants to be notified about when updated)
+//! - installed_apps (Apps and versions a contact has on each of their devices)
+//! - downloads (Download events with anonymised contacts for statistics)
+//! - reviews (Star ratings and comments per contact and app version)
+//! - reports (Reports of broken or malicious apps)
+//! - hidden_apps (Apps hidden from the store by an admin)
+//! - notified_versions (Versions of each app users were notified about)
+//! - jobs (Outgoing work like downloads and notifications, retried until it succeeds)
 //!
 //! A chat entry will be created when submitting a webxdc and holds a [SubmitChat].
 //! When the app is send to review, it will turn into a [ReviewChat] using the same row but with
@@ -14,12 +24,13 @@
 
 use crate::{
     bot::BotConfig,
-    request_handlers::{AppInfo, ChatType},
+    request_handlers::{AppInfo, AppTranslation, ChatType, Rating, Review, UpdatePolicy},
     utils::Webxdc,
 };
 use deltachat::{chat::ChatId, contact::ContactId, message::MsgId};
+use serde::Serialize;
 use sqlx::{migrate::Migrator, Connection, FromRow, Row, SqliteConnection};
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
 pub static MIGRATOR: Migrator = sqlx::migrate!();
 
@@ -37,6 +48,7 @@ pub struct DBAppInfo {
     pub xdc_blob_path: String,
     pub size: i64,
     pub version: u32,
+    pub critical: bool,
 }
 
 impl From<DBAppInfo> for AppInfo {
@@ -53,6 +65,10 @@ impl From<DBAppInfo> for AppInfo {
             xdc_blob_path: PathBuf::from(db_app.xdc_blob_path),
             size: db_app.size,
             version: db_app.version,
+            translations: vec![],
+            critical: db_app.critical,
+            downloads: None,
+            rating: None,
         }
     }
 }
@@ -81,6 +97,53 @@ impl TryFrom<DBBotConfig> for BotConfig {
 
 pub type RecordId = i32;
 
+/// A user report about an app version.
+#[derive(FromRow, Debug, Default, PartialEq)]
+pub struct Report {
+    pub id: RecordId,
+    pub app_id: String,
+    pub version: u32,
+    /// Contact who sent the report.
+    pub contact_id: u32,
+    pub reason: String,
+    pub timestamp: i64,
+}
+
+/// A stored job, see [crate::jobs::Job].
+#[derive(FromRow, Debug, Default, PartialEq)]
+pub struct JobRecord {
+    pub id: RecordId,
+    pub kind: String,
+    /// JSON encoded [crate::jobs::Job].
+    pub payload: String,
+    /// Number of failed attempts.
+    pub attempts: u32,
+    pub last_error: Option<String>,
+    /// Unix timestamp of the creation.
+    pub created: i64,
+}
+
+/// Versions of an app users were notified about, see [crate::notifications].
+#[derive(FromRow, Debug, Default, PartialEq)]
+pub struct NotifiedVersions {
+    /// Version subscribers were notified about.
+    pub version: u32,
+    /// Newest critical version users with older versions were notified about, 0 if none.
+    pub critical_version: u32,
+}
+
+/// Aggregated download statistics of one app.
+#[derive(FromRow, Serialize, Debug, PartialEq)]
+pub struct DownloadStats {
+    pub app_id: String,
+    /// Total number of downloads.
+    pub downloads: i64,
+    /// Number of different contacts who downloaded the app.
+    pub unique_users: i64,
+    /// Number of downloads since the requested timestamp.
+    pub recent_downloads: i64,
+}
+
 pub async fn set_config(c: &mut SqliteConnection, config: &BotConfig) -> anyhow::Result<()> {
     sqlx::query(
         "INSERT INTO config (genesis_qr, invite_qr, genesis_group, serial, store_xdc_version) VALUES (?, ?, ?, ?, ?)",
@@ -167,7 +230,7 @@ pub async fn create_app_info(
 ) -> anyhow::Result<()> {
     let mut trans = c.begin().await?;
     let next_serial = increase_get_serial(&mut trans).await?;
-    let res = sqlx::query("INSERT INTO app_infos (app_id, name, description, version, image, submitter_uri, xdc_blob_path, source_code_url, serial, date, size) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
+    let res = sqlx::query("INSERT INTO app_infos (app_id, name, description, version, image, submitter_uri, xdc_blob_path, source_code_url, serial, date, size, critical) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")
         .bind(app_info.app_id.as_str())
         .bind(app_info.name.as_str())
         .bind(&app_info.description)
@@ -179,20 +242,61 @@ pub async fn create_app_info(
         .bind(next_serial)
         .bind(app_info.date)
         .bind(app_info.size)
+        .bind(app_info.critical)
         .execute(&mut *trans)
         .await?;
     app_info.id = i32::try_from(res.last_insert_rowid())?;
+    for translation in &app_info.translations {
+        sqlx::query("INSERT INTO app_info_translations (app_info_id, locale, name, description) VALUES (?, ?, ?, ?)")
+            .bind(app_info.id)
+            .bind(&translation.locale)
+            .bind(&translation.name)
+            .bind(&translation.description)
+            .execute(&mut *trans)
+            .await?;
+    }
     trans.commit().await?;
     Ok(())
 }
 
-/// Get app_info by app_id.
+/// Get all translations of an app info.
+pub async fn get_app_translations(
+    c: &mut SqliteConnection,
+    app_info_id: RecordId,
+) -> sqlx::Result<Vec<AppTranslation>> {
+    sqlx::query("SELECT locale, name, description FROM app_info_translations WHERE app_info_id = ? ORDER BY locale")
+        .bind(app_info_id)
+        .fetch_all(c)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| AppTranslation {
+                    locale: row.get("locale"),
+                    name: row.get("name"),
+                    description: row.get("description"),
+                })
+                .collect()
+        })
+}
+
+/// Loads the translations of the given app infos.
+pub async fn load_translations(
+    c: &mut SqliteConnection,
+    app_infos: &mut [AppInfo],
+) -> sqlx::Result<()> {
+    for app_info in app_infos {
+        app_info.translations = get_app_translations(c, app_info.id).await?;
+    }
+    Ok(())
+}
+
+/// Get the newest app_info by app_id, hidden apps are not found.
 pub async fn get_app_info_for_app_id(
     c: &mut SqliteConnection,
     app_id: &str,
 ) -> sqlx::Result<AppInfo> {
     sqlx::query_as::<_, DBAppInfo>(
-        "SELECT * FROM app_infos WHERE app_id = ? ORDER BY version DESC LIMIT 1;",
+        "SELECT * FROM app_infos WHERE app_id = ? AND app_id NOT IN (SELECT app_id FROM hidden_apps) ORDER BY version DESC LIMIT 1;",
     )
     .bind(app_id)
     .fetch_one(c)
@@ -216,9 +320,39 @@ pub async fn maybe_get_greater_version(
     .map(|app| app.get(0))
 }
 
-#[cfg(test)]
+/// Like [maybe_get_greater_version], but ignores hidden apps.
+pub async fn maybe_get_greater_visible_version(
+    c: &mut SqliteConnection,
+    app_id: &str,
+    version: u32,
+) -> sqlx::Result<bool> {
+    sqlx::query(
+        "SELECT EXISTS(SELECT 1 FROM app_infos WHERE app_id = ? AND version > ? AND app_id NOT IN (SELECT app_id FROM hidden_apps))",
+    )
+    .bind(app_id)
+    .bind(version)
+    .fetch_one(c)
+    .await
+    .map(|row| row.get(0))
+}
+
+/// Returns whether an older version of the app exists.
+pub async fn app_has_older_version(
+    c: &mut SqliteConnection,
+    app_id: &str,
+    version: u32,
+) -> sqlx::Result<bool> {
+    sqlx::query("SELECT EXISTS(SELECT 1 FROM app_infos WHERE app_id = ? AND version < ?)")
+        .bind(app_id)
+        .bind(version)
+        .fetch_one(c)
+        .await
+        .map(|row| row.get(0))
+}
+
+/// Get all versions of all apps in the order they were added.
 pub async fn get_app_infos(c: &mut SqliteConnection) -> sqlx::Result<Vec<AppInfo>> {
-    sqlx::query_as::<_, DBAppInfo>("SELECT * FROM app_infos")
+    sqlx::query_as::<_, DBAppInfo>("SELECT * FROM app_infos ORDER BY serial, id")
         .fetch_all(c)
         .await
         .map(|app| app.into_iter().map(|a| a.into()).collect())
@@ -236,7 +370,8 @@ JOIN (
     FROM app_infos
     GROUP BY app_id
 ) b ON a.app_id = b.app_id AND a.version = b.latest_version
-WHERE a.serial > ?"#,
+WHERE a.serial > ?
+    AND a.app_id NOT IN (SELECT app_id FROM hidden_apps)"#,
     )
     .bind(serial)
     .fetch_all(c)
@@ -244,6 +379,221 @@ WHERE a.serial > ?"#,
     .map(|app| app.into_iter().map(|a| a.into()).collect())
 }
 
+/// Get the `app_id`s of all apps which are not hidden.
+pub async fn get_active_app_ids(c: &mut SqliteConnection) -> sqlx::Result<Vec<String>> {
+    sqlx::query(
+        "SELECT DISTINCT app_id FROM app_infos WHERE app_id NOT IN (SELECT app_id FROM hidden_apps) ORDER BY app_id",
+    )
+    .fetch_all(c)
+    .await
+    .map(|rows| rows.into_iter().map(|row| row.get("app_id")).collect())
+}
+
+/// Search the active app infos by app_id, name and description, including translations.
+pub async fn search_app_infos(c: &mut SqliteConnection, term: &str) -> sqlx::Result<Vec<AppInfo>> {
+    let pattern = format!(
+        "%{}%",
+        term.replace('\\', "\\\\")
+            .replace('%', "\\%")
+            .replace('_', "\\_")
+    );
+    sqlx::query_as::<_, DBAppInfo>(
+        r#"SELECT a.*
+FROM app_infos a
+JOIN (
+    SELECT app_id, MAX(version) AS latest_version
+    FROM app_infos
+    GROUP BY app_id
+) b ON a.app_id = b.app_id AND a.version = b.latest_version
+WHERE a.app_id NOT IN (SELECT app_id FROM hidden_apps)
+    AND (a.app_id LIKE ? ESCAPE '\'
+    OR a.name LIKE ? ESCAPE '\'
+    OR a.description LIKE ? ESCAPE '\'
+    OR EXISTS (
+        SELECT 1 FROM app_info_translations t
+        WHERE t.app_info_id = a.id
+            AND (t.name LIKE ? ESCAPE '\' OR t.description LIKE ? ESCAPE '\')
+    ))
+ORDER BY a.name"#,
+    )
+    .bind(&pattern)
+    .bind(&pattern)
+    .bind(&pattern)
+    .bind(&pattern)
+    .bind(&pattern)
+    .fetch_all(c)
+    .await
+    .map(|app| app.into_iter().map(|a| a.into()).collect())
+}
+
+/// Get the most recently added or updated active app infos.
+pub async fn get_recent_app_infos(
+    c: &mut SqliteConnection,
+    limit: u32,
+) -> sqlx::Result<Vec<AppInfo>> {
+    sqlx::query_as::<_, DBAppInfo>(
+        r#"SELECT a.*
+FROM app_infos a
+JOIN (
+    SELECT app_id, MAX(version) AS latest_version
+    FROM app_infos
+    GROUP BY app_id
+) b ON a.app_id = b.app_id AND a.version = b.latest_version
+WHERE a.app_id NOT IN (SELECT app_id FROM hidden_apps)
+ORDER BY a.serial DESC
+LIMIT ?"#,
+    )
+    .bind(limit)
+    .fetch_all(c)
+    .await
+    .map(|app| app.into_iter().map(|a| a.into()).collect())
+}
+
+/// Get all versions of an app, oldest first, including hidden ones.
+pub async fn get_app_versions(
+    c: &mut SqliteConnection,
+    app_id: &str,
+) -> sqlx::Result<Vec<AppInfo>> {
+    sqlx::query_as::<_, DBAppInfo>("SELECT * FROM app_infos WHERE app_id = ? ORDER BY version")
+        .bind(app_id)
+        .fetch_all(c)
+        .await
+        .map(|app| app.into_iter().map(|a| a.into()).collect())
+}
+
+/// Hides all versions of an app from the store, returns whether it was visible before.
+/// The app is hidden with a new serial, so frontends which already synced it can remove it.
+pub async fn hide_app(
+    c: &mut SqliteConnection,
+    app_id: &str,
+    timestamp: i64,
+) -> sqlx::Result<bool> {
+    let mut trans = c.begin().await?;
+    let result = sqlx::query("INSERT OR IGNORE INTO hidden_apps (app_id, timestamp) VALUES (?, ?)")
+        .bind(app_id)
+        .bind(timestamp)
+        .execute(&mut *trans)
+        .await?;
+    if result.rows_affected() > 0 {
+        let serial = increase_get_serial(&mut trans).await?;
+        sqlx::query("UPDATE hidden_apps SET serial = ? WHERE app_id = ?")
+            .bind(serial)
+            .bind(app_id)
+            .execute(&mut *trans)
+            .await?;
+    }
+    trans.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Get the `app_id`s of apps hidden after `serial`,
+/// which a frontend synced up to `serial` may still show.
+pub async fn get_hidden_app_ids_since(
+    c: &mut SqliteConnection,
+    serial: u32,
+) -> sqlx::Result<Vec<String>> {
+    sqlx::query(
+        r#"SELECT h.app_id FROM hidden_apps h
+WHERE h.serial > ?
+    AND EXISTS (SELECT 1 FROM app_infos a WHERE a.app_id = h.app_id AND a.serial <= ?)
+ORDER BY h.app_id"#,
+    )
+    .bind(serial)
+    .bind(serial)
+    .fetch_all(c)
+    .await
+    .map(|rows| rows.into_iter().map(|row| row.get("app_id")).collect())
+}
+
+/// Shows a hidden app again, returns whether it was hidden.
+/// The newest version gets a new serial, so frontends which already synced past it receive it again.
+pub async fn unhide_app(c: &mut SqliteConnection, app_id: &str) -> sqlx::Result<bool> {
+    let mut trans = c.begin().await?;
+    let result = sqlx::query("DELETE FROM hidden_apps WHERE app_id = ?")
+        .bind(app_id)
+        .execute(&mut *trans)
+        .await?;
+    if result.rows_affected() > 0 {
+        let serial = increase_get_serial(&mut trans).await?;
+        sqlx::query(
+            "UPDATE app_infos SET serial = ? WHERE id = (SELECT id FROM app_infos WHERE app_id = ? ORDER BY version DESC LIMIT 1)",
+        )
+        .bind(serial)
+        .bind(app_id)
+        .execute(&mut *trans)
+        .await?;
+    }
+    trans.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns whether an app is hidden from the store.
+pub async fn is_app_hidden(c: &mut SqliteConnection, app_id: &str) -> sqlx::Result<bool> {
+    sqlx::query("SELECT EXISTS(SELECT 1 FROM hidden_apps WHERE app_id = ?)")
+        .bind(app_id)
+        .fetch_one(c)
+        .await
+        .map(|row| row.get(0))
+}
+
+/// Marks an app version as critical update or removes the mark.
+/// Returns whether the version exists.
+/// The newest version gets a new serial, so frontends which already synced past it receive it again.
+pub async fn set_app_version_critical(
+    c: &mut SqliteConnection,
+    app_id: &str,
+    version: u32,
+    critical: bool,
+) -> sqlx::Result<bool> {
+    let mut trans = c.begin().await?;
+    let result = sqlx::query("UPDATE app_infos SET critical = ? WHERE app_id = ? AND version = ?")
+        .bind(critical)
+        .bind(app_id)
+        .bind(version)
+        .execute(&mut *trans)
+        .await?;
+    if result.rows_affected() > 0 {
+        let serial = increase_get_serial(&mut trans).await?;
+        sqlx::query(
+            "UPDATE app_infos SET serial = ? WHERE id = (SELECT id FROM app_infos WHERE app_id = ? ORDER BY version DESC LIMIT 1)",
+        )
+        .bind(serial)
+        .bind(app_id)
+        .execute(&mut *trans)
+        .await?;
+    }
+    trans.commit().await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns the newest version of an app which is a critical update, if any.
+pub async fn get_critical_version(
+    c: &mut SqliteConnection,
+    app_id: &str,
+) -> sqlx::Result<Option<u32>> {
+    sqlx::query("SELECT MAX(version) AS version FROM app_infos WHERE app_id = ? AND critical = 1")
+        .bind(app_id)
+        .fetch_one(c)
+        .await
+        .map(|row| row.get("version"))
+}
+
+/// Returns whether a version newer than `version` is a critical update.
+pub async fn has_critical_update(
+    c: &mut SqliteConnection,
+    app_id: &str,
+    version: u32,
+) -> sqlx::Result<bool> {
+    sqlx::query(
+        "SELECT EXISTS(SELECT 1 FROM app_infos WHERE app_id = ? AND version > ? AND critical = 1)",
+    )
+    .bind(app_id)
+    .bind(version)
+    .fetch_one(c)
+    .await
+    .map(|row| row.get(0))
+}
+
 pub async fn app_exists(c: &mut SqliteConnection, app_id: &str) -> sqlx::Result<bool> {
     sqlx::query("SELECT EXISTS(SELECT 1 FROM app_infos WHERE app_id = ?)")
         .bind(app_id)
@@ -252,6 +602,17 @@ pub async fn app_exists(c: &mut SqliteConnection, app_id: &str) -> sqlx::Result<
         .map(|row| row.get(0))
 }
 
+/// Like [app_exists], but ignores hidden apps.
+pub async fn visible_app_exists(c: &mut SqliteConnection, app_id: &str) -> sqlx::Result<bool> {
+    sqlx::query(
+        "SELECT EXISTS(SELECT 1 FROM app_infos WHERE app_id = ? AND app_id NOT IN (SELECT app_id FROM hidden_apps))",
+    )
+    .bind(app_id)
+    .fetch_one(c)
+    .await
+    .map(|row| row.get(0))
+}
+
 /// Returns wheter an [AppInfo] with given version exists for the app.
 pub async fn app_version_exists(
     c: &mut SqliteConnection,
@@ -273,123 +634,1233 @@ pub async fn get_last_serial(c: &mut SqliteConnection) -> sqlx::Result<i32> {
         .map(|a| a.get("serial"))
 }
 
-/// Sets the webxdc version for some sent webxdc.
-pub async fn set_webxdc_version(
+/// Subscribes a contact to update notifications of an app.
+pub async fn add_subscription(
     c: &mut SqliteConnection,
-    msg: MsgId,
-    version: u32,
-    webxdc: Webxdc,
+    contact_id: ContactId,
+    app_id: &str,
 ) -> sqlx::Result<()> {
-    sqlx::query(
-        "INSERT OR REPLACE INTO webxdc_versions (msg_id, version, webxdc) VALUES (?, ?, ?)",
-    )
-    .bind(msg.to_u32())
-    .bind(version)
-    .bind(webxdc)
-    .execute(c)
-    .await?;
+    sqlx::query("INSERT OR IGNORE INTO subscriptions (contact_id, app_id) VALUES (?, ?)")
+        .bind(contact_id.to_u32())
+        .bind(app_id)
+        .execute(c)
+        .await?;
     Ok(())
 }
 
-/// Gets the webxdc version for some sent webxdc.
-pub async fn get_webxdc_version(
+pub async fn remove_subscription(
     c: &mut SqliteConnection,
-    msg: MsgId,
-) -> sqlx::Result<(Webxdc, u32)> {
-    sqlx::query("SELECT * FROM webxdc_versions WHERE msg_id = ?")
-        .bind(msg.to_u32())
-        .fetch_one(c)
+    contact_id: ContactId,
+    app_id: &str,
+) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM subscriptions WHERE contact_id = ? AND app_id = ?")
+        .bind(contact_id.to_u32())
+        .bind(app_id)
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Get the app_ids a contact is subscribed to.
+pub async fn get_subscriptions(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+) -> sqlx::Result<Vec<String>> {
+    sqlx::query("SELECT app_id FROM subscriptions WHERE contact_id = ? ORDER BY app_id")
+        .bind(contact_id.to_u32())
+        .fetch_all(c)
         .await
-        .map(|a| (a.get("webxdc"), a.get("version")))
+        .map(|rows| rows.into_iter().map(|row| row.get("app_id")).collect())
 }
 
-#[cfg(test)]
-#[allow(clippy::unwrap_used)]
-mod tests {
-    use super::*;
-    use crate::utils::AddType;
-    use sqlx::{Connection, SqliteConnection};
-    use std::vec;
+/// Get all contacts subscribed to an app.
+pub async fn get_subscribers(
+    c: &mut SqliteConnection,
+    app_id: &str,
+) -> sqlx::Result<Vec<ContactId>> {
+    sqlx::query("SELECT contact_id FROM subscriptions WHERE app_id = ?")
+        .bind(app_id)
+        .fetch_all(c)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| ContactId::new(row.get("contact_id")))
+                .collect()
+        })
+}
 
-    #[tokio::test]
-    async fn test_create_load_config() {
-        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
-        MIGRATOR.run(&mut conn).await.unwrap();
+/// Records that a contact has a version of an app on one of their devices.
+/// A device never goes back to an older version, it keeps the greatest one.
+pub async fn set_installed_app(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+    device: &str,
+    app_id: &str,
+    version: u32,
+    timestamp: i64,
+) -> sqlx::Result<()> {
+    sqlx::query("INSERT INTO installed_apps (contact_id, device, app_id, version, timestamp) VALUES (?, ?, ?, ?, ?) ON CONFLICT (contact_id, device, app_id) DO UPDATE SET version = MAX(version, excluded.version), timestamp = excluded.timestamp")
+        .bind(contact_id.to_u32())
+        .bind(device)
+        .bind(app_id)
+        .bind(version)
+        .bind(timestamp)
+        .execute(c)
+        .await?;
+    Ok(())
+}
 
-        let config = BotConfig {
-            genesis_qr: "genesis_qr".to_string(),
-            invite_qr: "invite_qr".to_string(),
-            genesis_group: ChatId::new(1),
-            serial: 0,
-            store_xdc_version: "1.1.0".to_string(),
-        };
-        set_config(&mut conn, &config).await.unwrap();
-        let loaded_config = get_config(&mut conn).await.unwrap();
-        assert_eq!(config, loaded_config);
-    }
+/// Replaces the installed apps of a device with a full list reported by it.
+/// Apps missing from the list were removed from the device.
+pub async fn set_installed_apps(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+    device: &str,
+    apps: &[(String, u32)],
+    timestamp: i64,
+) -> sqlx::Result<()> {
+    let mut trans = c.begin().await?;
+    sqlx::query("DELETE FROM installed_apps WHERE contact_id = ? AND device = ?")
+        .bind(contact_id.to_u32())
+        .bind(device)
+        .execute(&mut *trans)
+        .await?;
+    for (app_id, version) in apps {
+        set_installed_app(&mut trans, contact_id, device, app_id, *version, timestamp).await?;
+    }
+    trans.commit().await?;
+    Ok(())
+}
+
+/// Get the app_ids and versions of all apps a contact has installed on a device,
+/// or on any device if none is given.
+/// An app installed on multiple devices is returned with the greatest version.
+pub async fn get_installed_apps(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+    device: Option<&str>,
+) -> sqlx::Result<Vec<(String, u32)>> {
+    sqlx::query("SELECT app_id, MAX(version) AS version FROM installed_apps WHERE contact_id = ?1 AND (?2 IS NULL OR device = ?2) GROUP BY app_id ORDER BY app_id")
+        .bind(contact_id.to_u32())
+        .bind(device)
+        .fetch_all(c)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| (row.get("app_id"), row.get("version")))
+                .collect()
+        })
+}
+
+/// Get all contacts which have no device with `version` or newer of an app,
+/// but an older version on at least one device.
+pub async fn get_contacts_with_older_version(
+    c: &mut SqliteConnection,
+    app_id: &str,
+    version: u32,
+) -> sqlx::Result<Vec<ContactId>> {
+    sqlx::query("SELECT contact_id FROM installed_apps WHERE app_id = ? GROUP BY contact_id HAVING MAX(version) < ?")
+        .bind(app_id)
+        .bind(version)
+        .fetch_all(c)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| ContactId::new(row.get("contact_id")))
+                .collect()
+        })
+}
+
+/// Get the greatest version of an app a contact has installed on any device, if any.
+pub async fn get_installed_version(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+    app_id: &str,
+) -> sqlx::Result<Option<u32>> {
+    sqlx::query(
+        "SELECT MAX(version) AS version FROM installed_apps WHERE contact_id = ? AND app_id = ?",
+    )
+    .bind(contact_id.to_u32())
+    .bind(app_id)
+    .fetch_one(c)
+    .await
+    .map(|row| row.get("version"))
+}
+
+pub async fn get_notified_serial(c: &mut SqliteConnection) -> sqlx::Result<u32> {
+    sqlx::query("SELECT notified_serial FROM config")
+        .fetch_one(c)
+        .await
+        .map(|row| row.get("notified_serial"))
+}
+
+pub async fn set_notified_serial(c: &mut SqliteConnection, serial: u32) -> sqlx::Result<()> {
+    sqlx::query("UPDATE config SET notified_serial = ?")
+        .bind(serial)
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Get the versions of an app users were notified about, all 0 if none.
+pub async fn get_notified_versions(
+    c: &mut SqliteConnection,
+    app_id: &str,
+) -> sqlx::Result<NotifiedVersions> {
+    sqlx::query_as::<_, NotifiedVersions>(
+        "SELECT version, critical_version FROM notified_versions WHERE app_id = ?",
+    )
+    .bind(app_id)
+    .fetch_optional(c)
+    .await
+    .map(Option::unwrap_or_default)
+}
+
+pub async fn set_notified_versions(
+    c: &mut SqliteConnection,
+    app_id: &str,
+    versions: &NotifiedVersions,
+) -> sqlx::Result<()> {
+    sqlx::query("INSERT INTO notified_versions (app_id, version, critical_version) VALUES (?, ?, ?) ON CONFLICT (app_id) DO UPDATE SET version = excluded.version, critical_version = excluded.critical_version")
+        .bind(app_id)
+        .bind(versions.version)
+        .bind(versions.critical_version)
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Gets the salt for anonymising contacts, creating it with `new_salt` if not set yet.
+pub async fn get_or_create_stats_salt(
+    c: &mut SqliteConnection,
+    new_salt: impl FnOnce() -> String,
+) -> sqlx::Result<String> {
+    let salt: String = sqlx::query("SELECT stats_salt FROM config")
+        .fetch_one(&mut *c)
+        .await?
+        .get("stats_salt");
+    if !salt.is_empty() {
+        return Ok(salt);
+    }
+    // Another call might have set it meanwhile, the salt must never change once used.
+    // Writing first locks the database until the salt is read again.
+    let mut trans = c.begin().await?;
+    sqlx::query("UPDATE config SET stats_salt = ? WHERE stats_salt = ''")
+        .bind(new_salt())
+        .execute(&mut *trans)
+        .await?;
+    let salt = sqlx::query("SELECT stats_salt FROM config")
+        .fetch_one(&mut *trans)
+        .await?
+        .get("stats_salt");
+    trans.commit().await?;
+    Ok(salt)
+}
+
+pub async fn record_download(
+    c: &mut SqliteConnection,
+    app_id: &str,
+    version: u32,
+    contact_hash: &str,
+    timestamp: i64,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT INTO downloads (app_id, version, timestamp, contact_hash) VALUES (?, ?, ?, ?)",
+    )
+    .bind(app_id)
+    .bind(version)
+    .bind(timestamp)
+    .bind(contact_hash)
+    .execute(c)
+    .await?;
+    Ok(())
+}
+
+/// Get the download statistics of all apps, most downloaded first.
+/// `recent_downloads` counts the downloads since the given unix timestamp.
+pub async fn get_download_stats(
+    c: &mut SqliteConnection,
+    since: i64,
+) -> sqlx::Result<Vec<DownloadStats>> {
+    sqlx::query_as::<_, DownloadStats>(
+        r#"SELECT app_id,
+    COUNT(*) AS downloads,
+    COUNT(DISTINCT contact_hash) AS unique_users,
+    SUM(CASE WHEN timestamp >= ? THEN 1 ELSE 0 END) AS recent_downloads
+FROM downloads
+GROUP BY app_id
+ORDER BY downloads DESC, app_id"#,
+    )
+    .bind(since)
+    .fetch_all(c)
+    .await
+}
+
+/// Get the total number of downloads per app_id.
+pub async fn get_download_counts(c: &mut SqliteConnection) -> sqlx::Result<HashMap<String, u32>> {
+    sqlx::query("SELECT app_id, COUNT(*) AS downloads FROM downloads GROUP BY app_id")
+        .fetch_all(c)
+        .await
+        .map(|rows| {
+            rows.into_iter()
+                .map(|row| (row.get("app_id"), row.get("downloads")))
+                .collect()
+        })
+}
+
+/// Adds the review of a contact for an app version, replacing an earlier one for the same version.
+pub async fn upsert_review(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+    review: &Review,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"INSERT INTO reviews (app_id, version, contact_id, stars, comment, timestamp) VALUES (?, ?, ?, ?, ?, ?)
+ON CONFLICT (app_id, version, contact_id) DO UPDATE SET stars = excluded.stars, comment = excluded.comment, timestamp = excluded.timestamp"#,
+    )
+    .bind(&review.app_id)
+    .bind(review.version)
+    .bind(contact_id.to_u32())
+    .bind(review.stars)
+    .bind(&review.comment)
+    .bind(review.date)
+    .execute(c)
+    .await?;
+    Ok(())
+}
+
+/// Get the newest reviews of an app over all versions.
+pub async fn get_reviews(
+    c: &mut SqliteConnection,
+    app_id: &str,
+    limit: u32,
+) -> sqlx::Result<Vec<Review>> {
+    sqlx::query_as::<_, Review>(
+        "SELECT id, app_id, version, stars, comment, timestamp AS date FROM reviews WHERE app_id = ? ORDER BY timestamp DESC, id DESC LIMIT ?",
+    )
+    .bind(app_id)
+    .bind(limit)
+    .fetch_all(c)
+    .await
+}
+
+/// Removes a review, returns whether it existed.
+pub async fn remove_review(c: &mut SqliteConnection, id: RecordId) -> sqlx::Result<bool> {
+    let result = sqlx::query("DELETE FROM reviews WHERE id = ?")
+        .bind(id)
+        .execute(c)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Get the rating of an app over all versions, if it has been rated.
+pub async fn get_rating(c: &mut SqliteConnection, app_id: &str) -> sqlx::Result<Option<Rating>> {
+    sqlx::query_as::<_, Rating>(
+        "SELECT AVG(stars) AS average, COUNT(*) AS count FROM reviews WHERE app_id = ? GROUP BY app_id",
+    )
+    .bind(app_id)
+    .fetch_optional(c)
+    .await
+}
+
+/// Get the ratings of all rated apps keyed by app_id.
+pub async fn get_ratings(c: &mut SqliteConnection) -> sqlx::Result<HashMap<String, Rating>> {
+    sqlx::query(
+        "SELECT app_id, AVG(stars) AS average, COUNT(*) AS count FROM reviews GROUP BY app_id",
+    )
+    .fetch_all(c)
+    .await
+    .map(|rows| {
+        rows.into_iter()
+            .map(|row| {
+                let rating = Rating {
+                    average: row.get("average"),
+                    count: row.get("count"),
+                };
+                (row.get("app_id"), rating)
+            })
+            .collect()
+    })
+}
+
+/// Stores a report and returns its id.
+pub async fn add_report(c: &mut SqliteConnection, report: &Report) -> anyhow::Result<RecordId> {
+    let result = sqlx::query(
+        "INSERT INTO reports (app_id, version, contact_id, reason, timestamp) VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(&report.app_id)
+    .bind(report.version)
+    .bind(report.contact_id)
+    .bind(&report.reason)
+    .bind(report.timestamp)
+    .execute(c)
+    .await?;
+    Ok(RecordId::try_from(result.last_insert_rowid())?)
+}
+
+/// Get all unresolved reports, oldest first.
+pub async fn get_open_reports(c: &mut SqliteConnection) -> sqlx::Result<Vec<Report>> {
+    sqlx::query_as::<_, Report>(
+        "SELECT id, app_id, version, contact_id, reason, timestamp FROM reports WHERE resolved = 0 ORDER BY id",
+    )
+    .fetch_all(c)
+    .await
+}
+
+/// Marks a report as resolved, returns whether an unresolved report existed.
+pub async fn resolve_report(c: &mut SqliteConnection, id: RecordId) -> sqlx::Result<bool> {
+    let result = sqlx::query("UPDATE reports SET resolved = 1 WHERE id = ? AND resolved = 0")
+        .bind(id)
+        .execute(c)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Stores a job due at `timestamp` and returns its id.
+pub async fn add_job(
+    c: &mut SqliteConnection,
+    kind: &str,
+    payload: &str,
+    timestamp: i64,
+) -> anyhow::Result<RecordId> {
+    let result =
+        sqlx::query("INSERT INTO jobs (kind, payload, next_attempt, created) VALUES (?, ?, ?, ?)")
+            .bind(kind)
+            .bind(payload)
+            .bind(timestamp)
+            .bind(timestamp)
+            .execute(c)
+            .await?;
+    Ok(RecordId::try_from(result.last_insert_rowid())?)
+}
+
+/// Get up to `limit` jobs which are due at `now`, oldest first.
+pub async fn get_due_jobs(
+    c: &mut SqliteConnection,
+    now: i64,
+    limit: u32,
+) -> sqlx::Result<Vec<JobRecord>> {
+    sqlx::query_as::<_, JobRecord>(
+        "SELECT id, kind, payload, attempts, last_error, created FROM jobs WHERE dead = 0 AND next_attempt <= ? ORDER BY id LIMIT ?",
+    )
+    .bind(now)
+    .bind(limit)
+    .fetch_all(c)
+    .await
+}
+
+/// Get the time of the next attempt of the job due next.
+pub async fn get_next_job_attempt(c: &mut SqliteConnection) -> sqlx::Result<Option<i64>> {
+    sqlx::query("SELECT MIN(next_attempt) FROM jobs WHERE dead = 0")
+        .fetch_one(c)
+        .await
+        .map(|row| row.get(0))
+}
+
+/// Removes a finished job.
+pub async fn remove_job(c: &mut SqliteConnection, id: RecordId) -> sqlx::Result<()> {
+    sqlx::query("DELETE FROM jobs WHERE id = ?")
+        .bind(id)
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Records a failed attempt of a job and schedules the next one at `next_attempt`,
+/// or marks the job as dead if `next_attempt` is `None`.
+pub async fn record_job_failure(
+    c: &mut SqliteConnection,
+    id: RecordId,
+    error: &str,
+    next_attempt: Option<i64>,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "UPDATE jobs SET attempts = attempts + 1, last_error = ?, next_attempt = COALESCE(?, next_attempt), dead = ? WHERE id = ?",
+    )
+    .bind(error)
+    .bind(next_attempt)
+    .bind(next_attempt.is_none())
+    .bind(id)
+    .execute(c)
+    .await?;
+    Ok(())
+}
+
+/// Get all jobs which failed too often, oldest first.
+pub async fn get_dead_jobs(c: &mut SqliteConnection) -> sqlx::Result<Vec<JobRecord>> {
+    sqlx::query_as::<_, JobRecord>(
+        "SELECT id, kind, payload, attempts, last_error, created FROM jobs WHERE dead = 1 ORDER BY id",
+    )
+    .fetch_all(c)
+    .await
+}
+
+/// Schedules a dead job again at `timestamp`, returns whether a dead job existed.
+pub async fn retry_dead_job(
+    c: &mut SqliteConnection,
+    id: RecordId,
+    timestamp: i64,
+) -> sqlx::Result<bool> {
+    let result = sqlx::query(
+        "UPDATE jobs SET dead = 0, attempts = 0, next_attempt = ? WHERE id = ? AND dead = 1",
+    )
+    .bind(timestamp)
+    .bind(id)
+    .execute(c)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Sets the preferred locale of a contact.
+pub async fn set_contact_locale(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+    locale: &str,
+) -> sqlx::Result<()> {
+    sqlx::query("INSERT INTO contact_settings (contact_id, locale) VALUES (?, ?) ON CONFLICT (contact_id) DO UPDATE SET locale = excluded.locale")
+        .bind(contact_id.to_u32())
+        .bind(locale)
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Gets the preferred locale of a contact, if any.
+pub async fn get_contact_locale(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+) -> sqlx::Result<Option<String>> {
+    sqlx::query("SELECT locale FROM contact_settings WHERE contact_id = ?")
+        .bind(contact_id.to_u32())
+        .fetch_optional(c)
+        .await
+        .map(|row| row.and_then(|row| row.get("locale")))
+}
+
+/// Sets how updates are delivered to a contact.
+pub async fn set_update_policy(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+    policy: UpdatePolicy,
+) -> sqlx::Result<()> {
+    sqlx::query("INSERT INTO contact_settings (contact_id, update_policy) VALUES (?, ?) ON CONFLICT (contact_id) DO UPDATE SET update_policy = excluded.update_policy")
+        .bind(contact_id.to_u32())
+        .bind(policy)
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Gets how updates are delivered to a contact, [UpdatePolicy::Auto] if never set.
+pub async fn get_update_policy(
+    c: &mut SqliteConnection,
+    contact_id: ContactId,
+) -> sqlx::Result<UpdatePolicy> {
+    sqlx::query("SELECT update_policy FROM contact_settings WHERE contact_id = ?")
+        .bind(contact_id.to_u32())
+        .fetch_optional(c)
+        .await
+        .map(|row| {
+            row.and_then(|row| row.get::<Option<UpdatePolicy>, _>("update_policy"))
+                .unwrap_or_default()
+        })
+}
+
+/// Sets the webxdc version for some sent webxdc.
+pub async fn set_webxdc_version(
+    c: &mut SqliteConnection,
+    msg: MsgId,
+    version: u32,
+    webxdc: Webxdc,
+) -> sqlx::Result<()> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO webxdc_versions (msg_id, version, webxdc) VALUES (?, ?, ?)",
+    )
+    .bind(msg.to_u32())
+    .bind(version)
+    .bind(webxdc)
+    .execute(c)
+    .await?;
+    Ok(())
+}
+
+/// Gets the webxdc version for some sent webxdc.
+pub async fn get_webxdc_version(
+    c: &mut SqliteConnection,
+    msg: MsgId,
+) -> sqlx::Result<(Webxdc, u32)> {
+    sqlx::query("SELECT * FROM webxdc_versions WHERE msg_id = ?")
+        .bind(msg.to_u32())
+        .fetch_one(c)
+        .await
+        .map(|a| (a.get("webxdc"), a.get("version")))
+}
+
+/// Stores the protocol version a sent webxdc announced in its handshake.
+pub async fn set_protocol_version(
+    c: &mut SqliteConnection,
+    msg: MsgId,
+    protocol_version: u32,
+) -> sqlx::Result<()> {
+    sqlx::query("UPDATE webxdc_versions SET protocol_version = ? WHERE msg_id = ?")
+        .bind(protocol_version)
+        .bind(msg.to_u32())
+        .execute(c)
+        .await?;
+    Ok(())
+}
+
+/// Gets the protocol version of a sent webxdc, `None` if it never sent a handshake.
+pub async fn get_protocol_version(
+    c: &mut SqliteConnection,
+    msg: MsgId,
+) -> sqlx::Result<Option<u32>> {
+    sqlx::query("SELECT protocol_version FROM webxdc_versions WHERE msg_id = ?")
+        .bind(msg.to_u32())
+        .fetch_one(c)
+        .await
+        .map(|row| row.get("protocol_version"))
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+    use crate::utils::AddType;
+    use sqlx::{Connection, SqliteConnection};
+    use std::vec;
+
+    #[tokio::test]
+    async fn test_create_load_config() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+
+        let config = BotConfig {
+            genesis_qr: "genesis_qr".to_string(),
+            invite_qr: "invite_qr".to_string(),
+            genesis_group: ChatId::new(1),
+            serial: 0,
+            store_xdc_version: "1.1.0".to_string(),
+        };
+        set_config(&mut conn, &config).await.unwrap();
+        let loaded_config = get_config(&mut conn).await.unwrap();
+        assert_eq!(config, loaded_config);
+    }
+
+    #[tokio::test]
+    async fn test_create_get_chattype() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+
+        let chat_type = ChatType::Genesis;
+        let chat_id = ChatId::new(0);
+
+        set_chat_type(&mut conn, chat_id, chat_type).await.unwrap();
+        let loaded_chat_type = get_chat_type(&mut conn, chat_id).await.unwrap();
+        assert_eq!(chat_type, loaded_chat_type)
+    }
+
+    #[tokio::test]
+    async fn increase_serial() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+        let serial = increase_get_serial(&mut conn).await.unwrap();
+        assert_eq!(serial, 1);
+    }
+
+    #[tokio::test]
+    async fn sent_webxdc_version_set_get() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+
+        let msg = MsgId::new(1);
+        set_webxdc_version(&mut conn, msg, 1, Webxdc::Store)
+            .await
+            .unwrap();
+        let (_, loaded_version) = get_webxdc_version(&mut conn, msg).await.unwrap();
+        assert_eq!(loaded_version, 1);
+
+        assert_eq!(get_protocol_version(&mut conn, msg).await.unwrap(), None);
+        set_protocol_version(&mut conn, msg, 2).await.unwrap();
+        assert_eq!(get_protocol_version(&mut conn, msg).await.unwrap(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_create_get_app_info() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+
+        let mut app_info = AppInfo {
+            size: 998887,
+            date: 1688835984521,
+            app_id: "app_id".to_string(),
+            id: 12,
+            version: 9,
+            name: "Sebastians coole app".to_string(),
+            submitter_uri: Some("https://example.com".to_string()),
+            source_code_url: Some("https://git.example.com/sebastian/app".to_string()),
+            image: "aaaaaaaaaaaaaaaaaaaaaaaaa".to_string(),
+            description: "This is a cool app".to_string(),
+            xdc_blob_path: PathBuf::from("xdc_blob_path"),
+            translations: vec![],
+            downloads: None,
+            rating: None,
+        };
+
+        create_app_info(&mut conn, &mut app_info).await.unwrap();
+
+        let loaded_app_info = get_app_info_for_app_id(&mut conn, &app_info.app_id)
+            .await
+            .unwrap();
+
+        assert_eq!(app_info, loaded_app_info);
+    }
+
+    #[tokio::test]
+    async fn test_app_translations() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+
+        let translation = AppTranslation {
+            locale: "de".to_string(),
+            name: "Kalender".to_string(),
+            description: "Ein Kalender".to_string(),
+        };
+        let mut app_info = AppInfo {
+            app_id: "calendar".to_string(),
+            name: "Calendar".to_string(),
+            translations: vec![translation.clone()],
+            ..Default::default()
+        };
+        create_app_info(&mut conn, &mut app_info).await.unwrap();
+
+        let mut app_infos = get_active_app_infos_since(&mut conn, 0).await.unwrap();
+        load_translations(&mut conn, &mut app_infos).await.unwrap();
+        let app_info = app_infos.first_mut().unwrap();
+        assert_eq!(app_info.translations, vec![translation]);
+
+        app_info.localize("de_AT");
+        assert_eq!(app_info.name, "Kalender");
+    }
+
+    #[tokio::test]
+    async fn test_search_and_recent() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+
+        for (app_id, name) in [("calendar", "Calendar"), ("hextris", "Hextris 100%")] {
+            create_app_info(
+                &mut conn,
+                &mut AppInfo {
+                    app_id: app_id.to_string(),
+                    name: name.to_string(),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        }
+
+        let found = search_app_infos(&mut conn, "calen").await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(search_app_infos(&mut conn, "%").await.unwrap().len(), 1);
+        assert!(search_app_infos(&mut conn, "chess")
+            .await
+            .unwrap()
+            .is_empty());
+
+        let recent = get_recent_app_infos(&mut conn, 1).await.unwrap();
+        assert_eq!(
+            recent.iter().map(|a| a.app_id.as_str()).collect::<Vec<_>>(),
+            vec!["hextris"]
+        );
+    }
 
     #[tokio::test]
-    async fn test_create_get_chattype() {
+    async fn test_subscriptions() {
         let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
         MIGRATOR.run(&mut conn).await.unwrap();
 
-        let chat_type = ChatType::Genesis;
-        let chat_id = ChatId::new(0);
+        let contact_id = ContactId::new(10);
+        add_subscription(&mut conn, contact_id, "calendar")
+            .await
+            .unwrap();
+        add_subscription(&mut conn, contact_id, "calendar")
+            .await
+            .unwrap();
+        add_subscription(&mut conn, contact_id, "2048")
+            .await
+            .unwrap();
+        assert_eq!(
+            get_subscriptions(&mut conn, contact_id).await.unwrap(),
+            vec!["2048".to_string(), "calendar".to_string()]
+        );
+        assert_eq!(
+            get_subscribers(&mut conn, "calendar").await.unwrap(),
+            vec![contact_id]
+        );
 
-        set_chat_type(&mut conn, chat_id, chat_type).await.unwrap();
-        let loaded_chat_type = get_chat_type(&mut conn, chat_id).await.unwrap();
-        assert_eq!(chat_type, loaded_chat_type)
+        remove_subscription(&mut conn, contact_id, "calendar")
+            .await
+            .unwrap();
+        assert!(get_subscribers(&mut conn, "calendar")
+            .await
+            .unwrap()
+            .is_empty());
     }
 
     #[tokio::test]
-    async fn increase_serial() {
+    async fn test_installed_apps() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+
+        let contact_id = ContactId::new(10);
+        let apps = vec![("poll".to_string(), 3), ("2048".to_string(), 1)];
+        set_installed_apps(&mut conn, contact_id, "phone", &apps, 100)
+            .await
+            .unwrap();
+        // An older copy on another device doesn't hide the newer one.
+        set_installed_app(&mut conn, contact_id, "laptop", "poll", 2, 200)
+            .await
+            .unwrap();
+        set_installed_app(&mut conn, ContactId::new(11), "", "chess", 1, 100)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_installed_apps(&mut conn, contact_id, None)
+                .await
+                .unwrap(),
+            vec![("2048".to_string(), 1), ("poll".to_string(), 3)]
+        );
+        assert_eq!(
+            get_installed_apps(&mut conn, contact_id, Some("laptop"))
+                .await
+                .unwrap(),
+            vec![("poll".to_string(), 2)]
+        );
+        assert_eq!(
+            get_installed_version(&mut conn, contact_id, "poll")
+                .await
+                .unwrap(),
+            Some(3)
+        );
+        assert_eq!(
+            get_installed_version(&mut conn, contact_id, "chess")
+                .await
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            get_contacts_with_older_version(&mut conn, "poll", 4)
+                .await
+                .unwrap(),
+            vec![contact_id]
+        );
+        assert!(get_contacts_with_older_version(&mut conn, "poll", 3)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // A full report of a device removes the apps missing from it.
+        let apps = vec![("2048".to_string(), 2)];
+        set_installed_apps(&mut conn, contact_id, "phone", &apps, 300)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_installed_apps(&mut conn, contact_id, None)
+                .await
+                .unwrap(),
+            vec![("2048".to_string(), 2), ("poll".to_string(), 2)]
+        );
+        assert_eq!(
+            get_contacts_with_older_version(&mut conn, "poll", 3)
+                .await
+                .unwrap(),
+            vec![contact_id]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_critical_versions() {
         let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
         MIGRATOR.run(&mut conn).await.unwrap();
         set_config(&mut conn, &BotConfig::default()).await.unwrap();
-        let serial = increase_get_serial(&mut conn).await.unwrap();
-        assert_eq!(serial, 1);
+
+        for (version, critical) in [(1, false), (2, true), (3, false)] {
+            let mut app_info = AppInfo {
+                app_id: "poll".to_string(),
+                version,
+                critical,
+                ..Default::default()
+            };
+            create_app_info(&mut conn, &mut app_info).await.unwrap();
+        }
+        assert!(has_critical_update(&mut conn, "poll", 1).await.unwrap());
+        assert!(!has_critical_update(&mut conn, "poll", 2).await.unwrap());
+        assert_eq!(
+            get_critical_version(&mut conn, "poll").await.unwrap(),
+            Some(2)
+        );
+        assert_eq!(
+            get_critical_version(&mut conn, "chess").await.unwrap(),
+            None
+        );
+
+        assert!(set_app_version_critical(&mut conn, "poll", 3, true)
+            .await
+            .unwrap());
+        assert!(!set_app_version_critical(&mut conn, "poll", 4, true)
+            .await
+            .unwrap());
+        assert!(has_critical_update(&mut conn, "poll", 2).await.unwrap());
+        assert_eq!(
+            get_critical_version(&mut conn, "poll").await.unwrap(),
+            Some(3)
+        );
+
+        // The mark is sent to frontends which already synced the newest version.
+        let app_infos = get_active_app_infos_since(&mut conn, 3).await.unwrap();
+        assert!(matches!(app_infos.as_slice(), [app_info] if app_info.critical));
+        assert_eq!(get_last_serial(&mut conn).await.unwrap(), 4);
     }
 
     #[tokio::test]
-    async fn sent_webxdc_version_set_get() {
+    async fn test_update_policy() {
         let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
         MIGRATOR.run(&mut conn).await.unwrap();
 
-        let msg = MsgId::new(1);
-        set_webxdc_version(&mut conn, msg, 1, Webxdc::Store)
+        let contact_id = ContactId::new(10);
+        assert_eq!(
+            get_update_policy(&mut conn, contact_id).await.unwrap(),
+            UpdatePolicy::Auto
+        );
+        set_contact_locale(&mut conn, contact_id, "de")
             .await
             .unwrap();
-        let (_, loaded_version) = get_webxdc_version(&mut conn, msg).await.unwrap();
-        assert_eq!(loaded_version, 1);
+        set_update_policy(&mut conn, contact_id, UpdatePolicy::Ask)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_update_policy(&mut conn, contact_id).await.unwrap(),
+            UpdatePolicy::Ask
+        );
+        assert_eq!(
+            get_contact_locale(&mut conn, contact_id).await.unwrap(),
+            Some("de".to_string())
+        );
     }
 
     #[tokio::test]
-    async fn test_create_get_app_info() {
+    async fn test_notified_serial() {
         let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
         MIGRATOR.run(&mut conn).await.unwrap();
         set_config(&mut conn, &BotConfig::default()).await.unwrap();
 
         let mut app_info = AppInfo {
-            size: 998887,
-            date: 1688835984521,
-            app_id: "app_id".to_string(),
-            id: 12,
-            version: 9,
-            name: "Sebastians coole app".to_string(),
-            submitter_uri: Some("https://example.com".to_string()),
-            source_code_url: Some("https://git.example.com/sebastian/app".to_string()),
-            image: "aaaaaaaaaaaaaaaaaaaaaaaaa".to_string(),
-            description: "This is a cool app".to_string(),
-            xdc_blob_path: PathBuf::from("xdc_blob_path"),
+            app_id: "testxdc".to_string(),
+            version: 1,
+            ..Default::default()
         };
-
         create_app_info(&mut conn, &mut app_info).await.unwrap();
+        assert!(!app_has_older_version(&mut conn, "testxdc", 1)
+            .await
+            .unwrap());
+        assert!(app_has_older_version(&mut conn, "testxdc", 2)
+            .await
+            .unwrap());
 
-        let loaded_app_info = get_app_info_for_app_id(&mut conn, &app_info.app_id)
+        assert_eq!(get_notified_serial(&mut conn).await.unwrap(), 0);
+        set_notified_serial(&mut conn, 1).await.unwrap();
+        assert_eq!(get_notified_serial(&mut conn).await.unwrap(), 1);
+
+        assert_eq!(
+            get_notified_versions(&mut conn, "testxdc").await.unwrap(),
+            NotifiedVersions::default()
+        );
+        let versions = NotifiedVersions {
+            version: 2,
+            critical_version: 1,
+        };
+        set_notified_versions(&mut conn, "testxdc", &versions)
             .await
             .unwrap();
+        assert_eq!(
+            get_notified_versions(&mut conn, "testxdc").await.unwrap(),
+            versions
+        );
+    }
 
-        assert_eq!(app_info, loaded_app_info);
+    #[tokio::test]
+    async fn test_download_stats() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+
+        let salt = get_or_create_stats_salt(&mut conn, || "salt".to_string())
+            .await
+            .unwrap();
+        assert_eq!(salt, "salt");
+        let salt = get_or_create_stats_salt(&mut conn, || "other".to_string())
+            .await
+            .unwrap();
+        assert_eq!(salt, "salt");
+
+        record_download(&mut conn, "calendar", 1, "a", 100)
+            .await
+            .unwrap();
+        record_download(&mut conn, "calendar", 2, "a", 200)
+            .await
+            .unwrap();
+        record_download(&mut conn, "calendar", 2, "b", 300)
+            .await
+            .unwrap();
+        record_download(&mut conn, "2048", 1, "b", 300)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            get_download_stats(&mut conn, 200).await.unwrap(),
+            vec![
+                DownloadStats {
+                    app_id: "calendar".to_string(),
+                    downloads: 3,
+                    unique_users: 2,
+                    recent_downloads: 2,
+                },
+                DownloadStats {
+                    app_id: "2048".to_string(),
+                    downloads: 1,
+                    unique_users: 1,
+                    recent_downloads: 1,
+                },
+            ]
+        );
+        assert_eq!(
+            get_download_counts(&mut conn)
+                .await
+                .unwrap()
+                .get("calendar"),
+            Some(&3)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reviews() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+
+        assert_eq!(get_rating(&mut conn, "calendar").await.unwrap(), None);
+
+        let mut review = Review {
+            app_id: "calendar".to_string(),
+            version: 1,
+            stars: 2,
+            comment: Some("Meh".to_string()),
+            date: 100,
+            ..Default::default()
+        };
+        upsert_review(&mut conn, ContactId::new(10), &review)
+            .await
+            .unwrap();
+        review.stars = 3;
+        review.date = 200;
+        upsert_review(&mut conn, ContactId::new(10), &review)
+            .await
+            .unwrap();
+        review.version = 2;
+        review.stars = 5;
+        review.comment = None;
+        review.date = 300;
+        upsert_review(&mut conn, ContactId::new(11), &review)
+            .await
+            .unwrap();
+
+        let reviews = get_reviews(&mut conn, "calendar", 10).await.unwrap();
+        assert_eq!(reviews.len(), 2);
+        assert_eq!(reviews.first().unwrap().stars, 5);
+        assert_eq!(reviews.last().unwrap().comment.as_deref(), Some("Meh"));
+        assert_eq!(
+            get_rating(&mut conn, "calendar").await.unwrap(),
+            Some(Rating {
+                average: 4.0,
+                count: 2
+            })
+        );
+        assert_eq!(get_ratings(&mut conn).await.unwrap().len(), 1);
+
+        let id = reviews.first().unwrap().id;
+        assert!(remove_review(&mut conn, id).await.unwrap());
+        assert!(!remove_review(&mut conn, id).await.unwrap());
+        assert_eq!(
+            get_rating(&mut conn, "calendar")
+                .await
+                .unwrap()
+                .unwrap()
+                .count,
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reports() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+
+        let mut report = Report {
+            app_id: "calendar".to_string(),
+            version: 1,
+            contact_id: 10,
+            reason: "Crashes on start".to_string(),
+            timestamp: 100,
+            ..Default::default()
+        };
+        report.id = add_report(&mut conn, &report).await.unwrap();
+        let other = add_report(&mut conn, &report).await.unwrap();
+        assert_ne!(report.id, other);
+
+        assert_eq!(
+            get_open_reports(&mut conn).await.unwrap().first(),
+            Some(&report)
+        );
+        assert!(resolve_report(&mut conn, report.id).await.unwrap());
+        assert!(!resolve_report(&mut conn, report.id).await.unwrap());
+        assert_eq!(get_open_reports(&mut conn).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_hide_app() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+        set_config(&mut conn, &BotConfig::default()).await.unwrap();
+
+        for (app_id, version) in [("calendar", 1), ("calendar", 2), ("poll", 1)] {
+            let mut app_info = AppInfo {
+                app_id: app_id.to_string(),
+                version,
+                ..Default::default()
+            };
+            create_app_info(&mut conn, &mut app_info).await.unwrap();
+        }
+
+        assert!(hide_app(&mut conn, "calendar", 100).await.unwrap());
+        assert!(!hide_app(&mut conn, "calendar", 100).await.unwrap());
+        assert!(is_app_hidden(&mut conn, "calendar").await.unwrap());
+        let active = get_active_app_infos_since(&mut conn, 0).await.unwrap();
+        assert_eq!(
+            active.iter().map(|a| a.app_id.as_str()).collect::<Vec<_>>(),
+            vec!["poll"]
+        );
+        assert_eq!(get_active_app_ids(&mut conn).await.unwrap(), vec!["poll"]);
+
+        // only frontends which synced calendar before it was hidden at serial 4 have to remove it
+        assert_eq!(
+            get_hidden_app_ids_since(&mut conn, 3).await.unwrap(),
+            vec!["calendar"]
+        );
+        assert!(get_hidden_app_ids_since(&mut conn, 4)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(get_hidden_app_ids_since(&mut conn, 0)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(get_app_info_for_app_id(&mut conn, "calendar")
+            .await
+            .is_err());
+        assert!(search_app_infos(&mut conn, "calendar")
+            .await
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            get_app_versions(&mut conn, "calendar").await.unwrap().len(),
+            2
+        );
+
+        // the unhidden app is sent again to frontends which synced up to serial 3
+        assert!(unhide_app(&mut conn, "calendar").await.unwrap());
+        assert!(!unhide_app(&mut conn, "calendar").await.unwrap());
+        assert!(get_hidden_app_ids_since(&mut conn, 3)
+            .await
+            .unwrap()
+            .is_empty());
+        let active = get_active_app_infos_since(&mut conn, 3).await.unwrap();
+        assert_eq!(
+            active
+                .iter()
+                .map(|a| (a.app_id.as_str(), a.version))
+                .collect::<Vec<_>>(),
+            vec![("calendar", 2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_jobs() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+
+        let first = add_job(&mut conn, "download", "{}", 100).await.unwrap();
+        let second = add_job(&mut conn, "notification", "{}", 200).await.unwrap();
+        assert_eq!(get_next_job_attempt(&mut conn).await.unwrap(), Some(100));
+        let due = get_due_jobs(&mut conn, 150, 10).await.unwrap();
+        assert_eq!(
+            due.iter().map(|job| job.id).collect::<Vec<_>>(),
+            vec![first]
+        );
+
+        record_job_failure(&mut conn, first, "offline", Some(300))
+            .await
+            .unwrap();
+        let due = get_due_jobs(&mut conn, 250, 10).await.unwrap();
+        assert_eq!(
+            due.iter().map(|job| job.id).collect::<Vec<_>>(),
+            vec![second]
+        );
+        remove_job(&mut conn, second).await.unwrap();
+
+        record_job_failure(&mut conn, first, "still offline", None)
+            .await
+            .unwrap();
+        assert!(get_due_jobs(&mut conn, 1000, 10).await.unwrap().is_empty());
+        assert_eq!(get_next_job_attempt(&mut conn).await.unwrap(), None);
+        let dead = get_dead_jobs(&mut conn).await.unwrap();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead.first().map(|job| job.attempts), Some(2));
+        assert_eq!(
+            dead.first().and_then(|job| job.last_error.as_deref()),
+            Some("still offline")
+        );
+
+        assert!(retry_dead_job(&mut conn, first, 1000).await.unwrap());
+        assert!(!retry_dead_job(&mut conn, first, 1000).await.unwrap());
+        assert_eq!(get_due_jobs(&mut conn, 1000, 10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_contact_locale() {
+        let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+        MIGRATOR.run(&mut conn).await.unwrap();
+
+        let contact_id = ContactId::new(10);
+        assert_eq!(
+            get_contact_locale(&mut conn, contact_id).await.unwrap(),
+            None
+        );
+
+        set_contact_locale(&mut conn, contact_id, "de")
+            .await
+            .unwrap();
+        set_contact_locale(&mut conn, contact_id, "fr")
+            .await
+            .unwrap();
+        assert_eq!(
+            get_contact_locale(&mut conn, contact_id).await.unwrap(),
+            Some("fr".to_string())
+        );
     }
 
     #[tokio::test]
@@ -451,6 +1922,11 @@ mod tests {
 
         assert!(super::app_exists(&mut conn, "testxdc").await.unwrap());
         assert!(!super::app_exists(&mut conn, "testxdc2").await.unwrap());
+        assert!(visible_app_exists(&mut conn, "testxdc").await.unwrap());
+
+        hide_app(&mut conn, "testxdc", 0).await.unwrap();
+        assert!(super::app_exists(&mut conn, "testxdc").await.unwrap());
+        assert!(!visible_app_exists(&mut conn, "testxdc").await.unwrap());
     }
 
     #[tokio::test]
@@ -490,5 +1966,17 @@ mod tests {
                 .await
                 .unwrap()
         );
+        assert!(
+            maybe_get_greater_visible_version(&mut conn, &app_info.app_id, app_info.version)
+                .await
+                .unwrap()
+        );
+
+        hide_app(&mut conn, &app_info.app_id, 0).await.unwrap();
+        assert!(
+            !maybe_get_greater_visible_version(&mut conn, &app_info.app_id, app_info.version)
+                .await
+                .unwrap()
+        );
     }
 }
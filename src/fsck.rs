@@ -0,0 +1,40 @@
+//! Consistency checks between the app index and the blob store.
+
+use anyhow::Result;
+use sqlx::SqliteConnection;
+use std::collections::HashSet;
+
+use crate::{blob_store::BlobStore, db, request_handlers::AppInfo};
+
+/// Problems found by [fsck].
+#[derive(Debug, Default)]
+pub struct FsckReport {
+    /// App infos whose xdc does not exist.
+    pub missing_blobs: Vec<AppInfo>,
+    /// Keys of blobs which belong to no app info.
+    pub orphaned_blobs: Vec<String>,
+}
+
+impl FsckReport {
+    pub fn problems(&self) -> usize {
+        self.missing_blobs.len() + self.orphaned_blobs.len()
+    }
+}
+
+/// Checks that every app info has a blob and every blob belongs to an app info.
+pub async fn fsck(conn: &mut SqliteConnection, blob_store: &dyn BlobStore) -> Result<FsckReport> {
+    let keys: HashSet<_> = blob_store.list().await?.into_iter().collect();
+    let mut report = FsckReport::default();
+    let mut referenced = HashSet::new();
+    for app_info in db::get_app_infos(conn).await? {
+        let key = app_info.blob_key()?.to_string();
+        if !keys.contains(&key) && !blob_store.exists(&key).await? {
+            report.missing_blobs.push(app_info);
+        }
+        referenced.insert(key);
+    }
+
+    report.orphaned_blobs = keys.difference(&referenced).cloned().collect();
+    report.orphaned_blobs.sort();
+    Ok(report)
+}
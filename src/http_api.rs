@@ -0,0 +1,319 @@
+//! Optional HTTP API for dashboards and automation.
+//!
+//! Read-only endpoints:
+//! - `GET /health`: whether the bot is connected and its database is reachable,
+//!   the same check as `/health` of the metrics, see [check_health]
+//! - `GET /apps`: the newest version of every visible app
+//! - `GET /apps/{app_id}`: details of an app, hidden apps only with the admin token
+//! - `GET /stats?days=30`: download statistics
+//!
+//! Admin endpoints, which require `Authorization: Bearer <http.token>`:
+//! - `POST /import/{file_name}?critical=true`: imports the xdc sent as request body,
+//!   optionally as critical update
+//! - `POST /apps/{app_id}/hide` and `POST /apps/{app_id}/unhide`
+
+use anyhow::Result;
+use hyper::{
+    body::HttpBody,
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    service::{make_service_fn, service_fn},
+    Body, HeaderMap, HeaderValue, Method, Request, Response, Server, StatusCode,
+};
+use log::{info, warn};
+use serde::Serialize;
+use serde_json::json;
+use std::{convert::Infallible, net::SocketAddr, path::Path, sync::Arc};
+use time::{Duration, OffsetDateTime};
+use tokio::sync::watch;
+
+use crate::{
+    bot::State, db, import::import_one, metrics::check_health, request_handlers::AppInfo,
+    utils::AddType, STATS_RECENT_DAYS,
+};
+
+/// An app with all information available to admins.
+#[derive(Serialize)]
+struct AppDetails {
+    #[serde(flatten)]
+    app_info: AppInfo,
+    hidden: bool,
+    /// All versions of the app, oldest first.
+    versions: Vec<u32>,
+}
+
+/// Serves the HTTP API on `addr` until `shutdown` is set or an error occurs.
+pub async fn serve(
+    state: Arc<State>,
+    addr: SocketAddr,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let make_service = make_service_fn(move |_conn| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(handle(&state, req).await) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr)?
+        .serve(make_service)
+        .with_graceful_shutdown(async move {
+            shutdown.changed().await.ok();
+        });
+    info!("HTTP API listening on {addr}");
+    server.await?;
+    Ok(())
+}
+
+async fn handle(state: &State, req: Request<Body>) -> Response<Body> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    match route(state, req).await {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("Failed to handle {method} {path}: {e:#}");
+            error(StatusCode::INTERNAL_SERVER_ERROR, "Internal error")
+        }
+    }
+}
+
+async fn route(state: &State, req: Request<Body>) -> Result<Response<Body>> {
+    let path = req.uri().path().to_string();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (req.method(), segments.as_slice()) {
+        (&Method::GET, ["health"]) => health(state).await,
+        (&Method::GET, ["apps"]) => apps(state).await,
+        (&Method::GET, ["apps", app_id]) => {
+            let admin = check_token(state, req.headers()).is_none();
+            app_details(state, app_id, admin).await
+        }
+        (&Method::GET, ["stats"]) => stats(state, req.uri().query()).await,
+        (&Method::POST, ["import", file_name]) => {
+            if let Some(response) = check_token(state, req.headers()) {
+                return Ok(response);
+            }
+            let critical = query_param(req.uri().query(), "critical") == Some("true");
+            import(state, file_name, critical, req.into_body()).await
+        }
+        (&Method::POST, ["apps", app_id, action @ ("hide" | "unhide")]) => {
+            if let Some(response) = check_token(state, req.headers()) {
+                return Ok(response);
+            }
+            set_hidden(state, app_id, *action == "hide").await
+        }
+        _ => Ok(error(StatusCode::NOT_FOUND, "Not found")),
+    }
+}
+
+async fn health(state: &State) -> Result<Response<Body>> {
+    Ok(match check_health(state).await {
+        Ok(serial) => json(StatusCode::OK, &json!({ "status": "ok", "serial": serial })),
+        Err(e) => error(StatusCode::SERVICE_UNAVAILABLE, &format!("{e:#}")),
+    })
+}
+
+async fn apps(state: &State) -> Result<Response<Body>> {
+    let conn = &mut *state.db.acquire().await?;
+    let mut app_infos = db::get_active_app_infos_since(conn, 0).await?;
+    let counts = db::get_download_counts(conn).await?;
+    let ratings = db::get_ratings(conn).await?;
+    for app_info in &mut app_infos {
+        app_info.downloads = Some(counts.get(&app_info.app_id).copied().unwrap_or_default());
+        app_info.rating = ratings.get(&app_info.app_id).copied();
+    }
+    Ok(json(StatusCode::OK, &app_infos))
+}
+
+/// Returns the details of an app, hidden apps are only found by admins.
+async fn app_details(state: &State, app_id: &str, admin: bool) -> Result<Response<Body>> {
+    let conn = &mut *state.db.acquire().await?;
+    let hidden = db::is_app_hidden(conn, app_id).await?;
+    let versions = db::get_app_versions(conn, app_id).await?;
+    let Some(newest) = versions.last().filter(|_| admin || !hidden) else {
+        return Ok(error(StatusCode::NOT_FOUND, "App not found"));
+    };
+    let mut app_info = newest.clone();
+    app_info.downloads = Some(
+        db::get_download_counts(conn)
+            .await?
+            .get(app_id)
+            .copied()
+            .unwrap_or_default(),
+    );
+    app_info.rating = db::get_rating(conn, app_id).await?;
+    let details = AppDetails {
+        app_info,
+        hidden,
+        versions: versions.iter().map(|app_info| app_info.version).collect(),
+    };
+    Ok(json(StatusCode::OK, &details))
+}
+
+async fn stats(state: &State, query: Option<&str>) -> Result<Response<Body>> {
+    let days = match query_param(query, "days") {
+        Some(days) => match days.parse::<i64>() {
+            Ok(days) if days > 0 => days,
+            _ => return Ok(error(StatusCode::BAD_REQUEST, "Invalid days")),
+        },
+        None => STATS_RECENT_DAYS,
+    };
+    let since = OffsetDateTime::now_utc() - Duration::days(days);
+    let stats =
+        db::get_download_stats(&mut *state.db.acquire().await?, since.unix_timestamp()).await?;
+    Ok(json(StatusCode::OK, &stats))
+}
+
+async fn import(
+    state: &State,
+    file_name: &str,
+    critical: bool,
+    mut body: Body,
+) -> Result<Response<Body>> {
+    let valid_name = file_name.ends_with(".xdc")
+        && file_name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'));
+    if !valid_name {
+        return Ok(error(StatusCode::BAD_REQUEST, "Invalid file name"));
+    }
+
+    let max_size = state.settings.limits.max_xdc_size;
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if u64::try_from(data.len() + chunk.len())? > max_size {
+            return Ok(error(StatusCode::PAYLOAD_TOO_LARGE, "xdc is too large"));
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    // `import_one` reads the xdc from a file
+    let dir = state
+        .settings
+        .data_dir
+        .join("http-import")
+        .join(rand::random::<u64>().to_string());
+    tokio::fs::create_dir_all(&dir).await?;
+    let result = import_file(state, &dir.join(file_name), data, critical).await;
+    if let Err(e) = tokio::fs::remove_dir_all(&dir).await {
+        warn!("Failed to remove {}: {e}", dir.display());
+    }
+
+    Ok(match result? {
+        Ok(add_type) => {
+            let result = match add_type {
+                AddType::Added => "added",
+                AddType::Updated => "updated",
+                AddType::Ignored => "ignored",
+            };
+            info!("Imported {file_name} via HTTP API: {result}");
+            json(StatusCode::OK, &json!({ "result": result }))
+        }
+        Err(e) => error(StatusCode::UNPROCESSABLE_ENTITY, &format!("{e:#}")),
+    })
+}
+
+/// Writes an uploaded xdc to `file` and imports it.
+/// The outer error is an internal one, the inner one the reason the xdc was rejected.
+async fn import_file(
+    state: &State,
+    file: &Path,
+    data: Vec<u8>,
+    critical: bool,
+) -> Result<Result<AddType>> {
+    tokio::fs::write(file, data).await?;
+    let conn = &mut *state.db.acquire().await?;
+    let max_size = state.settings.limits.max_xdc_size;
+    Ok(import_one(file, state.blob_store.as_ref(), max_size, critical, conn).await)
+}
+
+async fn set_hidden(state: &State, app_id: &str, hidden: bool) -> Result<Response<Body>> {
+    let conn = &mut *state.db.acquire().await?;
+    if !db::app_exists(conn, app_id).await? {
+        return Ok(error(StatusCode::NOT_FOUND, "App not found"));
+    }
+    let changed = if hidden {
+        db::hide_app(conn, app_id, OffsetDateTime::now_utc().unix_timestamp()).await?
+    } else {
+        db::unhide_app(conn, app_id).await?
+    };
+    if changed {
+        info!("Set hidden={hidden} for {app_id} via HTTP API");
+    }
+    Ok(json(
+        StatusCode::OK,
+        &json!({ "hidden": hidden, "changed": changed }),
+    ))
+}
+
+/// Returns an error response if the request is not authorized for admin endpoints.
+fn check_token(state: &State, headers: &HeaderMap) -> Option<Response<Body>> {
+    let Some(token) = &state.settings.http.token else {
+        return Some(error(StatusCode::FORBIDDEN, "Admin endpoints are disabled"));
+    };
+    let given = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match given {
+        Some(given) if constant_time_eq(given.as_bytes(), token.as_bytes()) => None,
+        _ => Some(error(StatusCode::UNAUTHORIZED, "Invalid token")),
+    }
+}
+
+/// Compares two byte strings in time independent of their content.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Returns the value of a query parameter, values are not percent-decoded.
+fn query_param<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+fn json<T: Serialize>(status: StatusCode, value: &T) -> Response<Body> {
+    match serde_json::to_vec(value) {
+        Ok(body) => {
+            let mut response = Response::new(Body::from(body));
+            *response.status_mut() = status;
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            response
+        }
+        Err(e) => {
+            warn!("Failed to serialize response: {e}");
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            response
+        }
+    }
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+    json(status, &json!({ "error": message }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_param() {
+        assert_eq!(query_param(Some("days=7&x=1"), "days"), Some("7"));
+        assert_eq!(query_param(Some("x=1"), "days"), None);
+        assert_eq!(query_param(None, "days"), None);
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
+}
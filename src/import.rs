@@ -6,13 +6,17 @@ use std::{
 };
 
 use crate::{
+    blob_store::BlobStore,
+    db,
     request_handlers::AppInfo,
-    utils::{maybe_upgrade_xdc, AddType},
+    utils::{is_valid_app_id, maybe_upgrade_xdc, sha256_hex, AddType},
 };
 
 pub async fn import_many(
     path: &Path,
-    xdcs_path: PathBuf,
+    blob_store: &dyn BlobStore,
+    max_size: u64,
+    critical: bool,
     conn: &mut SqliteConnection,
 ) -> anyhow::Result<()> {
     let dir_entry = std::fs::read_dir(path).context("Failed to read dir")?;
@@ -41,7 +45,7 @@ pub async fn import_many(
     let mut failed = Vec::new();
 
     for file in &xdcs {
-        match import_one(file, &xdcs_path, conn).await {
+        match import_one(file, blob_store, max_size, critical, conn).await {
             Ok(AddType::Added) => added.push(file),
             Ok(AddType::Updated) => updated.push(file),
             Ok(AddType::Ignored) => ignored.push(file),
@@ -69,11 +73,17 @@ pub async fn import_many(
 }
 
 /// Add a single webxdc to the store
+/// - Store it in the `blob_store` with its hash as key
 /// - Add it to the db
-/// - Copy it into the `dest` location
+///
+/// Versions which already exist are ignored without storing the file.
+/// Files larger than `max_size` bytes are rejected.
+/// With `critical` the version is marked as critical update of older versions.
 pub async fn import_one(
     file: &Path,
-    dest: &Path,
+    blob_store: &dyn BlobStore,
+    max_size: u64,
+    critical: bool,
     conn: &mut SqliteConnection,
 ) -> anyhow::Result<AddType> {
     if !file
@@ -84,17 +94,39 @@ pub async fn import_one(
         bail!("File does not end with .xdc");
     }
 
+    let size = fs::metadata(file)
+        .with_context(|| format!("Failed to read metadata of {}", file.display()))?
+        .len();
+    if size > max_size {
+        bail!(
+            "{} is {size} bytes, which exceeds the limit of {max_size} bytes",
+            file.display()
+        );
+    }
+
     let mut app_info = AppInfo::from_xdc(file)
         .await
         .context(anyhow::anyhow!("Failed to load {}", file.display()))?;
+    if !is_valid_app_id(&app_info.app_id) {
+        bail!(
+            "Invalid app_id {:?}, only letters, digits, '.', '_' and '-' are allowed",
+            app_info.app_id
+        );
+    }
     app_info.submitter_uri = Some("xdcstore".to_string());
+    app_info.critical = critical;
+    if db::app_version_exists(conn, &app_info.app_id, app_info.version).await? {
+        return Ok(AddType::Ignored);
+    }
 
-    // copy the file to the `dest`
-    let mut dest = PathBuf::from(dest);
-    dest.push(file.file_name().context("Direntry has no filename")?);
-    fs::copy(file, &dest)
-        .with_context(|| format!("Failed to copy {} to {}", file.display(), dest.display()))?;
-    app_info.xdc_blob_path = dest;
+    // the hash as key keeps xdcs with the same file name apart
+    let data = fs::read(file).with_context(|| format!("Failed to read {}", file.display()))?;
+    let key = format!("{}.xdc", sha256_hex(&data));
+    blob_store
+        .put(&key, data)
+        .await
+        .with_context(|| format!("Failed to store {}", file.display()))?;
+    app_info.xdc_blob_path = PathBuf::from(key);
 
     // Add it to the db
     maybe_upgrade_xdc(&mut app_info, conn).await
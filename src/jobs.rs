@@ -0,0 +1,310 @@
+//! Persistent queue for outgoing work.
+//!
+//! Downloads, update pushes and notifications are stored in the database before they are run,
+//! so they survive restarts. Failed jobs are retried with exponential backoff
+//! and kept as dead jobs after [MAX_ATTEMPTS], which can be listed with `xdcstore dead-jobs`.
+
+use anyhow::{bail, Context as _, Result};
+use deltachat::{
+    chat::ChatId,
+    contact::ContactId,
+    context::Context,
+    message::{Message, MsgId},
+};
+use futures::StreamExt;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use time::OffsetDateTime;
+use tokio::sync::watch;
+
+use crate::{
+    bot::State,
+    db::{self, JobRecord},
+    notifications,
+    request_handlers::store::handle_download,
+    utils::{format_date, send_response},
+};
+
+/// Number of failed attempts after which a job is given up.
+const MAX_ATTEMPTS: u32 = 8;
+/// Delay before the first retry, doubled for every further attempt.
+const RETRY_DELAY: i64 = 10;
+/// Maximum delay between two attempts.
+const MAX_RETRY_DELAY: i64 = 60 * 60;
+/// Maximum number of jobs loaded at once.
+const BATCH_SIZE: u32 = 50;
+/// Time after which the queue is checked even if no new job was added.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum Job {
+    /// Sends the newest version of an app to the store xdc `msg_id`
+    /// in response to a download request or as update of a cached app.
+    Download {
+        msg_id: u32,
+        app_id: String,
+        /// Contact the download is recorded for.
+        contact_id: Option<u32>,
+        /// Device the app is recorded as installed on.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        device: Option<String>,
+        /// ID of the request the download responds to.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request_id: Option<String>,
+    },
+    /// Notifies a subscriber about the newest version of an app.
+    Notification { contact_id: u32, app_id: String },
+    /// Asks a contact with an older version installed to update to the newest one.
+    CriticalUpdate { contact_id: u32, app_id: String },
+}
+
+impl Job {
+    fn kind(&self) -> &'static str {
+        match self {
+            Job::Download { .. } => "download",
+            Job::Notification { .. } => "notification",
+            Job::CriticalUpdate { .. } => "critical_update",
+        }
+    }
+
+    /// Returns the chat the job sends to, if it exists.
+    async fn chat_id(&self, context: &Context) -> Result<Option<ChatId>> {
+        match self {
+            Job::Download { msg_id, .. } => Ok(Some(
+                Message::load_from_db(context, MsgId::new(*msg_id))
+                    .await?
+                    .get_chat_id(),
+            )),
+            Job::Notification { contact_id, .. } | Job::CriticalUpdate { contact_id, .. } => {
+                ChatId::lookup_by_contact(context, ContactId::new(*contact_id)).await
+            }
+        }
+    }
+
+    async fn run(&self, context: &Context, state: &State) -> Result<()> {
+        match self {
+            Job::Download {
+                msg_id,
+                app_id,
+                contact_id,
+                device,
+                request_id,
+            } => {
+                let response = handle_download(
+                    state,
+                    app_id.clone(),
+                    contact_id.map(ContactId::new),
+                    device.as_deref().unwrap_or_default(),
+                )
+                .await;
+                send_response(
+                    context,
+                    MsgId::new(*msg_id),
+                    request_id.as_deref(),
+                    response,
+                )
+                .await
+            }
+            Job::Notification { contact_id, app_id } => {
+                notifications::notify(context, state, ContactId::new(*contact_id), app_id, false)
+                    .await
+            }
+            Job::CriticalUpdate { contact_id, app_id } => {
+                notifications::notify(context, state, ContactId::new(*contact_id), app_id, true)
+                    .await
+            }
+        }
+    }
+}
+
+/// Stores a job to be run by the [job_loop].
+/// [State::new_jobs] has to be notified afterwards, see [enqueue].
+pub async fn add(conn: &mut SqliteConnection, job: &Job) -> Result<()> {
+    db::add_job(
+        conn,
+        job.kind(),
+        &serde_json::to_string(job)?,
+        OffsetDateTime::now_utc().unix_timestamp(),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Stores a job and wakes up the [job_loop].
+pub async fn enqueue(state: &State, job: &Job) -> Result<()> {
+    add(&mut *state.db.acquire().await?, job).await?;
+    state.new_jobs.notify_one();
+    Ok(())
+}
+
+/// Runs due jobs until `shutdown` is set. Jobs left from a previous run are run first.
+pub async fn job_loop(context: Context, state: Arc<State>, mut shutdown: watch::Receiver<bool>) {
+    while !*shutdown.borrow() {
+        let delay = match run_due_jobs(&context, &state).await {
+            Ok(delay) => delay,
+            Err(e) => {
+                warn!("Failed to run jobs: {e:#}");
+                POLL_INTERVAL
+            }
+        };
+        tokio::select! {
+            _ = shutdown.changed() => break,
+            _ = state.new_jobs.notified() => {}
+            _ = tokio::time::sleep(delay) => {}
+        }
+    }
+}
+
+/// Runs all due jobs and returns the time until the next job is due.
+/// The jobs of different chats run in parallel, the jobs of a chat in the order they were added.
+async fn run_due_jobs(context: &Context, state: &State) -> Result<Duration> {
+    loop {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+        let jobs = db::get_due_jobs(&mut *state.db.acquire().await?, now, BATCH_SIZE).await?;
+        if jobs.is_empty() {
+            break;
+        }
+        let count = jobs.len();
+        let updated = futures::stream::iter(group_by_chat(context, jobs).await)
+            .map(|group| async move {
+                let mut updated = 0;
+                for record in group {
+                    match run_job(context, state, record).await {
+                        Ok(()) => updated += 1,
+                        Err(e) => warn!("Failed to update job: {e:#}"),
+                    }
+                }
+                updated
+            })
+            .buffer_unordered(state.settings.workers.count)
+            .fold(0, |sum, updated| async move { sum + updated })
+            .await;
+        // the jobs are still due, loading them again would spin
+        if updated == 0 {
+            bail!("None of {count} due jobs could be updated");
+        }
+    }
+
+    let next = db::get_next_job_attempt(&mut *state.db.acquire().await?).await?;
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+    Ok(match next {
+        Some(next) => {
+            Duration::from_secs(u64::try_from(next - now).unwrap_or(0).max(1)).min(POLL_INTERVAL)
+        }
+        None => POLL_INTERVAL,
+    })
+}
+
+/// Groups jobs by the chat they send to, keeping their order.
+/// Jobs whose chat is unknown get a group of their own.
+async fn group_by_chat(context: &Context, jobs: Vec<JobRecord>) -> Vec<Vec<JobRecord>> {
+    let mut groups: Vec<Vec<JobRecord>> = Vec::new();
+    let mut chat_groups = HashMap::new();
+    for record in jobs {
+        let chat_id = match serde_json::from_str::<Job>(&record.payload) {
+            Ok(job) => job.chat_id(context).await.ok().flatten(),
+            Err(_) => None,
+        };
+        match chat_id.and_then(|chat_id| chat_groups.get(&chat_id).copied()) {
+            Some(index) => {
+                if let Some(group) = groups.get_mut(index) {
+                    group.push(record);
+                }
+            }
+            None => {
+                if let Some(chat_id) = chat_id {
+                    chat_groups.insert(chat_id, groups.len());
+                }
+                groups.push(vec![record]);
+            }
+        }
+    }
+    groups
+}
+
+/// Runs a single job and removes it on success or records the failure.
+async fn run_job(context: &Context, state: &State, record: JobRecord) -> Result<()> {
+    let result = match serde_json::from_str::<Job>(&record.payload) {
+        Ok(job) => job.run(context, state).await,
+        Err(e) => Err(e).context("Invalid job"),
+    };
+    let conn = &mut *state.db.acquire().await?;
+    match result {
+        Ok(()) => db::remove_job(conn, record.id).await?,
+        Err(e) => {
+            let attempts = record.attempts + 1;
+            let next_attempt = (attempts < MAX_ATTEMPTS)
+                .then(|| OffsetDateTime::now_utc().unix_timestamp() + retry_delay(attempts));
+            match next_attempt {
+                Some(_) => warn!(
+                    "{} job {} failed (attempt {attempts}): {e:#}",
+                    record.kind, record.id
+                ),
+                None => warn!(
+                    "{} job {} failed {attempts} times, giving up: {e:#}",
+                    record.kind, record.id
+                ),
+            }
+            db::record_job_failure(conn, record.id, &format!("{e:#}"), next_attempt).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Returns the delay in seconds before the next attempt after `attempts` failed ones.
+fn retry_delay(attempts: u32) -> i64 {
+    RETRY_DELAY
+        .saturating_mul(1 << attempts.saturating_sub(1).min(20))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Formats dead jobs as a plain text list.
+pub fn format_dead_jobs(jobs: &[JobRecord]) -> String {
+    let mut list = String::new();
+    for job in jobs {
+        list.push_str(&format!(
+            "#{} {} created {}, {} attempts: {}\n    {}\n",
+            job.id,
+            job.kind,
+            format_date(job.created),
+            job.attempts,
+            job.last_error.as_deref().unwrap_or_default(),
+            job.payload
+        ));
+    }
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), 10);
+        assert_eq!(retry_delay(2), 20);
+        assert_eq!(retry_delay(4), 80);
+        assert_eq!(retry_delay(MAX_ATTEMPTS), 1280);
+        assert_eq!(retry_delay(40), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_job_serialization() {
+        let job = Job::Download {
+            msg_id: 10,
+            app_id: "poll".to_string(),
+            contact_id: None,
+            device: None,
+            request_id: None,
+        };
+        let json = serde_json::to_string(&job).unwrap_or_default();
+        assert_eq!(
+            json,
+            r#"{"type":"Download","msg_id":10,"app_id":"poll","contact_id":null}"#
+        );
+        assert_eq!(serde_json::from_str::<Job>(&json).ok(), Some(job));
+    }
+}
@@ -1,43 +1,73 @@
 #![warn(clippy::all, clippy::indexing_slicing, clippy::unwrap_used)]
+mod blob_store;
 mod bot;
+mod bundle;
+mod catalog;
 mod cli;
 mod db;
+mod fsck;
+mod http_api;
 mod import;
+mod jobs;
 mod messages;
+mod metrics;
+mod notifications;
+mod rate_limit;
 mod request_handlers;
+mod settings;
+mod sync;
 mod utils;
-use std::fs::create_dir_all;
+mod workers;
 use std::path::PathBuf;
 
-use anyhow::Context as _;
+use anyhow::{bail, Context as _};
 use bot::Bot;
 use build_script_file_gen::include_file_str;
 use clap::Parser;
 use cli::{BotActions, BotCli};
+use settings::Settings;
+use time::{Duration, OffsetDateTime};
 use tokio::signal;
-use utils::{project_dirs, AddType};
+use utils::AddType;
 
 const GENESIS_QR: &str = "genesis_invite_qr.png";
 const INVITE_QR: &str = "1o1_invite_qr.png";
 const STORE_XDC: &str = "store.xdc";
 const VERSION: &str = include_file_str!("VERSION");
+/// Number of days counted as recent in the download statistics.
+const STATS_RECENT_DAYS: i64 = 30;
+/// Time running requests get to finish on shutdown.
+const SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    env_logger::init();
     let cli = BotCli::parse();
+    let mut settings = Settings::load(cli.config.as_deref())?;
+    if let Some(data_dir) = &cli.data_dir {
+        settings.data_dir = data_dir.clone();
+    }
+    settings.validate().context("invalid config")?;
+
+    let mut logger = env_logger::Builder::from_default_env();
+    if std::env::var_os("RUST_LOG").is_none() {
+        if let Some(level) = &settings.logging.level {
+            logger.parse_filters(level);
+        }
+    }
+    logger.init();
 
     match &cli.action {
-        BotActions::Import { path } => {
+        BotActions::Import { path, critical } => {
             let path = PathBuf::from(path);
-            let bot = Bot::new().await.context("failed to create bot")?;
-            let xdcs_dir = project_dirs()?.config_dir().to_path_buf().join("xdcs");
-            create_dir_all(&xdcs_dir)?;
+            let max_size = settings.limits.max_xdc_size;
+            let bot = Bot::new(settings).await.context("failed to create bot")?;
 
             if path.is_file() {
                 match import::import_one(
                     path.as_path(),
-                    &xdcs_dir,
+                    bot.blob_store(),
+                    max_size,
+                    *critical,
                     &mut *bot.get_db_connection().await?,
                 )
                 .await?
@@ -49,7 +79,9 @@ async fn main() -> anyhow::Result<()> {
             } else if path.is_dir() {
                 import::import_many(
                     path.as_path(),
-                    xdcs_dir,
+                    bot.blob_store(),
+                    max_size,
+                    *critical,
                     &mut *bot.get_db_connection().await?,
                 )
                 .await?;
@@ -57,8 +89,26 @@ async fn main() -> anyhow::Result<()> {
                 eprintln!("{} is not a file or directory", path.display());
             }
         }
+        BotActions::MarkCritical {
+            app_id,
+            version,
+            unset,
+        } => {
+            let bot = Bot::new(settings).await.context("Failed to create bot")?;
+            let conn = &mut *bot.get_db_connection().await?;
+            if !db::set_app_version_critical(conn, app_id, *version, !unset).await? {
+                bail!("There is no version {version} of {app_id}");
+            }
+            if *unset {
+                println!("{app_id} version {version} is no longer a critical update");
+            } else {
+                println!(
+                    "Marked {app_id} version {version} as critical update, users of older versions will be notified"
+                );
+            }
+        }
         BotActions::ShowQr => {
-            let bot = Bot::new().await.context("Failed to create bot")?;
+            let bot = Bot::new(settings).await.context("Failed to create bot")?;
             match db::get_config(&mut *bot.get_db_connection().await?).await {
                 Ok(config) => {
                     println!("Genesis invite qr:");
@@ -70,11 +120,155 @@ async fn main() -> anyhow::Result<()> {
             }
         }
         BotActions::Version => print!("{}", VERSION),
+        BotActions::Stats { days } => {
+            let bot = Bot::new(settings).await.context("Failed to create bot")?;
+            let since = OffsetDateTime::now_utc() - Duration::days(*days);
+            let stats = db::get_download_stats(
+                &mut *bot.get_db_connection().await?,
+                since.unix_timestamp(),
+            )
+            .await?;
+            if stats.is_empty() {
+                println!("No apps have been downloaded yet.");
+            } else {
+                print!("{}", utils::format_download_stats(&stats, *days));
+            }
+        }
+        BotActions::ExportStore { archive } => {
+            let bot = Bot::new(settings).await.context("Failed to create bot")?;
+            let count = bundle::export_store(
+                &mut *bot.get_db_connection().await?,
+                archive,
+                bot.blob_store(),
+            )
+            .await?;
+            println!("Exported {count} app versions to {}", archive.display());
+        }
+        BotActions::ImportStore { archive } => {
+            let max_size = settings.limits.max_xdc_size;
+            let bot = Bot::new(settings).await.context("Failed to create bot")?;
+            let summary = bundle::import_store(
+                &mut *bot.get_db_connection().await?,
+                archive,
+                bot.blob_store(),
+                max_size,
+            )
+            .await?;
+            println!(
+                "Imported {} app versions, skipped {} existing ones",
+                summary.added, summary.skipped
+            );
+        }
+        BotActions::ExportCatalog { dir } => {
+            let bot = Bot::new(settings).await.context("Failed to create bot")?;
+            let count = catalog::export_catalog(
+                &mut *bot.get_db_connection().await?,
+                dir,
+                bot.blob_store(),
+            )
+            .await?;
+            println!("Exported {count} apps to {}", dir.display());
+        }
+        BotActions::Sync { from, dry_run } => {
+            let options = sync::SyncOptions {
+                max_size: settings.limits.max_xdc_size,
+                max_bundle_size: settings.limits.max_bundle_size,
+                tmp_dir: settings.data_dir.join("sync"),
+                dry_run: *dry_run,
+            };
+            let bot = Bot::new(settings).await.context("Failed to create bot")?;
+            sync::sync(
+                &mut *bot.get_db_connection().await?,
+                bot.blob_store(),
+                from,
+                &options,
+            )
+            .await?;
+        }
+        BotActions::Fsck => {
+            let bot = Bot::new(settings).await.context("Failed to create bot")?;
+            let blob_store = bot.blob_store();
+            let report = fsck::fsck(&mut *bot.get_db_connection().await?, blob_store).await?;
+            for app_info in &report.missing_blobs {
+                println!(
+                    "Missing xdc of {} version {}: {}",
+                    app_info.app_id,
+                    app_info.version,
+                    blob_store.location(app_info.blob_key()?)
+                );
+            }
+            for key in &report.orphaned_blobs {
+                println!("Unused xdc: {}", blob_store.location(key));
+            }
+            if report.problems() > 0 {
+                bail!("Found {} problems", report.problems());
+            }
+            println!("No problems found.");
+        }
+        BotActions::DeadJobs { retry } => {
+            let bot = Bot::new(settings).await.context("Failed to create bot")?;
+            let conn = &mut *bot.get_db_connection().await?;
+            if let Some(id) = retry {
+                let now = OffsetDateTime::now_utc().unix_timestamp();
+                if !db::retry_dead_job(conn, *id, now).await? {
+                    bail!("There is no dead job #{id}");
+                }
+                println!("Job #{id} will be retried by the running bot.");
+            } else {
+                let dead_jobs = db::get_dead_jobs(conn).await?;
+                if dead_jobs.is_empty() {
+                    println!("No dead jobs.");
+                } else {
+                    print!("{}", jobs::format_dead_jobs(&dead_jobs));
+                }
+            }
+        }
+        BotActions::Status => {
+            let addr = settings
+                .metrics
+                .listen
+                .context("metrics.listen is not set in the config")?;
+            let snapshot = metrics::fetch_status(addr).await?;
+            print!(
+                "{}",
+                snapshot.to_status(OffsetDateTime::now_utc().unix_timestamp())
+            );
+            if !snapshot.is_connected() {
+                bail!("Bot is not connected");
+            }
+        }
+        BotActions::CheckConfig => {
+            println!("Config is valid.");
+            println!("Data directory: {}", settings.data_dir.display());
+            println!("Access mode: {:?}", settings.access.mode);
+            match &settings.account.addr {
+                Some(addr) => println!("Account: {addr}"),
+                None => println!(
+                    "Account: not set, the bot can only start if its account is already configured"
+                ),
+            }
+        }
         BotActions::Start => {
-            let mut bot = Bot::new().await.context("Failed to create bot")?;
+            let mut bot = Bot::new(settings).await.context("Failed to create bot")?;
             bot.start().await;
-            signal::ctrl_c().await?;
+            shutdown_signal().await?;
+            bot.stop(SHUTDOWN_TIMEOUT).await;
+        }
+    }
+    Ok(())
+}
+
+/// Waits for SIGINT (Ctrl+C) or SIGTERM.
+async fn shutdown_signal() -> anyhow::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut sigterm = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = signal::ctrl_c() => result?,
+            _ = sigterm.recv() => {}
         }
     }
+    #[cfg(not(unix))]
+    signal::ctrl_c().await?;
     Ok(())
 }
@@ -1,3 +1,182 @@
-pub fn store_message() -> &'static str {
-    r#"Welcome to the webxdc store!"#
+//! Localized texts the bot sends to users.
+//!
+//! Every locale has its own [Catalog], English is used as fallback.
+//! Texts may contain `{}` placeholders which are replaced with [fill].
+
+/// All texts of one locale.
+pub struct Catalog {
+    /// Sent along with the store.xdc.
+    pub welcome: &'static str,
+    /// Reply for users who are not allowed to use an invite-only store.
+    pub invite_only: &'static str,
+    /// Reply to `/help`.
+    pub help: &'static str,
+    /// Reply to an unknown command, takes the command.
+    pub unknown_command: &'static str,
+    /// Reply to a command with missing arguments, takes the usage.
+    pub usage: &'static str,
+    /// Header of the `/search` results, takes the search term.
+    pub search_results: &'static str,
+    /// Reply to a `/search` without results, takes the search term.
+    pub search_no_results: &'static str,
+    /// Header of the `/new` results.
+    pub new_apps: &'static str,
+    /// Reply to `/new` if the store is empty.
+    pub no_apps: &'static str,
+    /// Reply if an app does not exist, takes the app id.
+    pub app_not_found: &'static str,
+    /// Details sent for `/info`, takes the version, size in KiB and source code url.
+    pub app_details: &'static str,
+    /// Reply to `/language`, takes the locale.
+    pub language_set: &'static str,
+    /// Reply to `/subscribe`, takes the app id.
+    pub subscribed: &'static str,
+    /// Reply to `/unsubscribe`, takes the app id.
+    pub unsubscribed: &'static str,
+    /// Notification sent to subscribers, takes the app name and version.
+    pub app_updated: &'static str,
+    /// Notification sent to users of older versions, takes the app name and version.
+    pub critical_update: &'static str,
+    /// Additional commands listed by `/help` in the genesis group.
+    pub help_genesis: &'static str,
+    /// Reply to `/stats` if nothing was downloaded yet.
+    pub no_downloads: &'static str,
+    /// Reply to `/reviews` if the app has no reviews, takes the app id.
+    pub no_reviews: &'static str,
+    /// Reply to `/removereview`, takes the review id.
+    pub review_removed: &'static str,
+    /// Reply to `/removereview` if the review does not exist, takes the review id.
+    pub review_not_found: &'static str,
+    /// Report forwarded to the genesis group, takes the report id, app name, app id, version,
+    /// reporter, reason and again the report id.
+    pub report_received: &'static str,
+    /// Reply to `/reports` if there are no open reports.
+    pub no_reports: &'static str,
+    /// Reply to `/resolvereport`, takes the report id.
+    pub report_resolved: &'static str,
+    /// Reply to `/resolvereport` if there is no open report, takes the report id.
+    pub report_not_found: &'static str,
+}
+
+const EN: Catalog = Catalog {
+    welcome: "Welcome to the webxdc store!",
+    invite_only: "This store is invite-only. Please join using the invite QR-code you got from the store operators.",
+    help: "Available commands:\n\
+        /search <term> - search the app index\n\
+        /info <app_id> - show details of an app\n\
+        /get <app_id> - receive an app in this chat\n\
+        /new - show recently added apps\n\
+        /subscribe <app_id> - get notified about updates of an app\n\
+        /unsubscribe <app_id> - stop update notifications of an app\n\
+        /language <code> - set your language, e.g. /language de\n\
+        /version - show the bot version\n\
+        /help - show this help\n\n\
+        Send any other message to receive a new store app.",
+    unknown_command: "Unknown command {}. Send /help for a list of commands.",
+    usage: "Usage: {}",
+    search_results: "Apps matching \"{}\":",
+    search_no_results: "No apps found for \"{}\".",
+    new_apps: "Recently added apps:",
+    no_apps: "The store has no apps yet.",
+    app_not_found: "There is no app with the id {}.",
+    app_details: "Version: {}\nSize: {} KiB\nSource code: {}",
+    language_set: "Language set to {}.",
+    subscribed: "You will be notified about updates of {}.",
+    unsubscribed: "You will no longer be notified about updates of {}.",
+    app_updated: "{} was updated to version {}.",
+    critical_update: "Please update {} to version {}, it fixes a critical problem of the version you use.",
+    help_genesis: "Administrative commands:\n\
+        /stats - show download statistics\n\
+        /reviews <app_id> - list the reviews of an app\n\
+        /removereview <review_id> - remove an abusive review\n\
+        /reports - list open app reports\n\
+        /resolvereport <report_id> - mark a report as resolved",
+    no_downloads: "No apps have been downloaded yet.",
+    no_reviews: "{} has no reviews yet.",
+    review_removed: "Review #{} was removed.",
+    review_not_found: "There is no review #{}.",
+    report_received: "Report #{} for {} ({}) version {} by {}:\n{}\n\nSend /resolvereport {} once it is handled.",
+    no_reports: "There are no open reports.",
+    report_resolved: "Report #{} was resolved.",
+    report_not_found: "There is no open report #{}.",
+};
+
+const DE: Catalog = Catalog {
+    welcome: "Willkommen im Webxdc-Store!",
+    invite_only: "Dieser Store ist nur auf Einladung nutzbar. Bitte tritt über den Einladungs-QR-Code bei, den du von den Betreibern erhalten hast.",
+    help: "Verfügbare Befehle:\n\
+        /search <Begriff> - App-Verzeichnis durchsuchen\n\
+        /info <app_id> - Details einer App anzeigen\n\
+        /get <app_id> - App in diesem Chat erhalten\n\
+        /new - kürzlich hinzugefügte Apps anzeigen\n\
+        /subscribe <app_id> - über Updates einer App benachrichtigt werden\n\
+        /unsubscribe <app_id> - Update-Benachrichtigungen einer App beenden\n\
+        /language <Code> - Sprache festlegen, z.B. /language en\n\
+        /version - Bot-Version anzeigen\n\
+        /help - diese Hilfe anzeigen\n\n\
+        Sende eine beliebige andere Nachricht, um eine neue Store-App zu erhalten.",
+    unknown_command: "Unbekannter Befehl {}. Sende /help für eine Liste der Befehle.",
+    usage: "Verwendung: {}",
+    search_results: "Apps zu \"{}\":",
+    search_no_results: "Keine Apps zu \"{}\" gefunden.",
+    new_apps: "Kürzlich hinzugefügte Apps:",
+    no_apps: "Der Store enthält noch keine Apps.",
+    app_not_found: "Es gibt keine App mit der ID {}.",
+    app_details: "Version: {}\nGröße: {} KiB\nQuellcode: {}",
+    language_set: "Sprache auf {} gesetzt.",
+    subscribed: "Du wirst über Updates von {} benachrichtigt.",
+    unsubscribed: "Du wirst nicht mehr über Updates von {} benachrichtigt.",
+    app_updated: "{} wurde auf Version {} aktualisiert.",
+    critical_update: "Bitte aktualisiere {} auf Version {}, sie behebt ein kritisches Problem deiner Version.",
+    help_genesis: "Administrative Befehle:\n\
+        /stats - Download-Statistiken anzeigen\n\
+        /reviews <app_id> - Bewertungen einer App auflisten\n\
+        /removereview <review_id> - missbräuchliche Bewertung entfernen\n\
+        /reports - offene Meldungen zu Apps auflisten\n\
+        /resolvereport <report_id> - Meldung als erledigt markieren",
+    no_downloads: "Es wurden noch keine Apps heruntergeladen.",
+    no_reviews: "{} hat noch keine Bewertungen.",
+    review_removed: "Bewertung #{} wurde entfernt.",
+    review_not_found: "Es gibt keine Bewertung #{}.",
+    report_received: "Meldung #{} zu {} ({}) Version {} von {}:\n{}\n\nSende /resolvereport {}, sobald sie erledigt ist.",
+    no_reports: "Es gibt keine offenen Meldungen.",
+    report_resolved: "Meldung #{} wurde als erledigt markiert.",
+    report_not_found: "Es gibt keine offene Meldung #{}.",
+};
+
+/// Available catalogs keyed by language code.
+const CATALOGS: &[(&str, &Catalog)] = &[("en", &EN), ("de", &DE)];
+
+/// Returns the catalog for the given locale, falling back to English.
+pub fn catalog(locale: Option<&str>) -> &'static Catalog {
+    locale
+        .map(|locale| language(&normalize_locale(locale)).to_string())
+        .and_then(|language| {
+            CATALOGS
+                .iter()
+                .find(|(code, _)| *code == language)
+                .map(|(_, catalog)| *catalog)
+        })
+        .unwrap_or(&EN)
+}
+
+/// Replaces the `{}` placeholders of a text with the given arguments in order.
+pub fn fill(text: &str, args: &[&str]) -> String {
+    let mut parts = text.split("{}");
+    let mut filled = parts.next().unwrap_or_default().to_string();
+    for (part, arg) in parts.zip(args.iter().chain(std::iter::repeat(&""))) {
+        filled.push_str(arg);
+        filled.push_str(part);
+    }
+    filled
+}
+
+/// Normalizes locales like `de_AT` to the lowercase BCP 47 form `de-at`.
+pub fn normalize_locale(locale: &str) -> String {
+    locale.trim().replace('_', "-").to_lowercase()
+}
+
+/// Returns the language part of a normalized locale, e.g. `de` for `de-at`.
+pub fn language(locale: &str) -> &str {
+    locale.split('-').next().unwrap_or(locale)
 }
@@ -0,0 +1,406 @@
+//! Health and metrics of the running bot.
+//!
+//! Served on `metrics.listen` as Prometheus text on `/metrics`, as JSON on `/status`
+//! for the `status` subcommand and as plain health check on `/health`.
+
+use anyhow::{bail, Context as _, Result};
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, HeaderValue, Method, Request, Response, Server, StatusCode,
+};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::Write as _,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicI64, AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use time::OffsetDateTime;
+use tokio::sync::watch;
+
+use crate::{bot::State, db};
+
+/// Delta Chat connectivity from which on the bot counts as connected,
+/// see `deltachat::scheduler::connectivity::Connectivity`.
+const CONNECTIVITY_WORKING: u32 = 3000;
+/// Time a client gets to send the request header.
+const HEADER_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Counters and gauges updated by the bot.
+pub struct Metrics {
+    started: i64,
+    connectivity: AtomicU32,
+    last_event: AtomicI64,
+    requests: Mutex<BTreeMap<&'static str, u64>>,
+    sent_xdcs: AtomicU64,
+    sent_xdc_bytes: AtomicU64,
+    errors: AtomicU64,
+    queued_events: AtomicI64,
+    queue_full: AtomicU64,
+}
+
+/// Point-in-time copy of the [Metrics].
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    /// Unix time the bot was started.
+    pub started: i64,
+    /// Delta Chat connectivity, `0` if unknown.
+    pub connectivity: u32,
+    /// Unix time at which the last Delta Chat event was handled, `0` if none was handled yet.
+    pub last_event: i64,
+    /// Number of store requests per request type.
+    pub requests: BTreeMap<String, u64>,
+    /// Number of xdcs sent to users.
+    pub sent_xdcs: u64,
+    /// Total size of the xdcs sent to users.
+    pub sent_xdc_bytes: u64,
+    /// Number of events which failed to be handled.
+    pub errors: u64,
+    /// Number of events waiting for a worker.
+    pub queued_events: i64,
+    /// Number of times an event had to wait for space in a full worker queue.
+    pub queue_full: u64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            started: OffsetDateTime::now_utc().unix_timestamp(),
+            connectivity: AtomicU32::new(0),
+            last_event: AtomicI64::new(0),
+            requests: Mutex::new(BTreeMap::new()),
+            sent_xdcs: AtomicU64::new(0),
+            sent_xdc_bytes: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            queued_events: AtomicI64::new(0),
+            queue_full: AtomicU64::new(0),
+        }
+    }
+
+    pub fn set_connectivity(&self, connectivity: u32) {
+        self.connectivity.store(connectivity, Ordering::Relaxed);
+    }
+
+    pub fn record_event(&self) {
+        self.last_event.store(
+            OffsetDateTime::now_utc().unix_timestamp(),
+            Ordering::Relaxed,
+        );
+    }
+
+    pub fn record_request(&self, request: &'static str) {
+        let mut requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        *requests.entry(request).or_default() += 1;
+    }
+
+    /// Records an xdc of `size` bytes sent to a user.
+    pub fn record_sent_xdc(&self, size: usize) {
+        self.sent_xdcs.fetch_add(1, Ordering::Relaxed);
+        self.sent_xdc_bytes
+            .fetch_add(u64::try_from(size).unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    pub fn record_error(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_enqueued(&self) {
+        self.queued_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_dequeued(&self) {
+        self.queued_events.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn record_queue_full(&self) {
+        self.queue_full.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let requests = self.requests.lock().unwrap_or_else(|e| e.into_inner());
+        Snapshot {
+            started: self.started,
+            connectivity: self.connectivity.load(Ordering::Relaxed),
+            last_event: self.last_event.load(Ordering::Relaxed),
+            requests: requests
+                .iter()
+                .map(|(request, count)| (request.to_string(), *count))
+                .collect(),
+            sent_xdcs: self.sent_xdcs.load(Ordering::Relaxed),
+            sent_xdc_bytes: self.sent_xdc_bytes.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            queued_events: self.queued_events.load(Ordering::Relaxed),
+            queue_full: self.queue_full.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Snapshot {
+    pub fn is_connected(&self) -> bool {
+        self.connectivity >= CONNECTIVITY_WORKING
+    }
+
+    /// Renders the snapshot in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut text = String::new();
+        let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+            let _ = writeln!(text, "# HELP xdcstore_{name} {help}");
+            let _ = writeln!(text, "# TYPE xdcstore_{name} {kind}");
+            for (labels, value) in samples {
+                let _ = writeln!(text, "xdcstore_{name}{labels} {value}");
+            }
+        };
+        let sample = |value: String| [(String::new(), value)];
+        metric(
+            "start_time_seconds",
+            "gauge",
+            "Unix time the bot was started.",
+            &sample(self.started.to_string()),
+        );
+        metric(
+            "connectivity",
+            "gauge",
+            "Delta Chat connectivity: 1000 not connected, 2000 connecting, 3000 working, 4000 connected.",
+            &sample(self.connectivity.to_string()),
+        );
+        metric(
+            "last_event_timestamp_seconds",
+            "gauge",
+            "Unix time at which the last Delta Chat event was handled.",
+            &sample(self.last_event.to_string()),
+        );
+        let requests: Vec<_> = self
+            .requests
+            .iter()
+            .map(|(request, count)| (format!("{{request=\"{request}\"}}"), count.to_string()))
+            .collect();
+        metric(
+            "requests_total",
+            "counter",
+            "Store requests by type.",
+            &requests,
+        );
+        metric(
+            "sent_xdcs_total",
+            "counter",
+            "xdcs sent to users.",
+            &sample(self.sent_xdcs.to_string()),
+        );
+        metric(
+            "sent_xdc_bytes_total",
+            "counter",
+            "Total size of the xdcs sent to users.",
+            &sample(self.sent_xdc_bytes.to_string()),
+        );
+        metric(
+            "errors_total",
+            "counter",
+            "Delta Chat events and store requests which failed to be handled.",
+            &sample(self.errors.to_string()),
+        );
+        metric(
+            "queued_events",
+            "gauge",
+            "Events waiting for a worker.",
+            &sample(self.queued_events.to_string()),
+        );
+        metric(
+            "queue_full_total",
+            "counter",
+            "Events which had to wait for space in a full worker queue.",
+            &sample(self.queue_full.to_string()),
+        );
+        text
+    }
+
+    /// Formats the snapshot for humans, `now` is the current unix time.
+    pub fn to_status(&self, now: i64) -> String {
+        let connectivity = match self.connectivity {
+            0 => "unknown",
+            c if c >= 4000 => "connected",
+            c if c >= CONNECTIVITY_WORKING => "working",
+            c if c >= 2000 => "connecting",
+            _ => "not connected",
+        };
+        let mut status = format!(
+            "Connectivity: {connectivity}\nUptime: {}\n",
+            format_duration(now - self.started)
+        );
+        if self.last_event > 0 {
+            let _ = writeln!(
+                status,
+                "Last event: {} ago",
+                format_duration(now - self.last_event)
+            );
+        } else {
+            status.push_str("Last event: none\n");
+        }
+        let _ = writeln!(
+            status,
+            "Sent xdcs: {} ({} KiB)\nErrors: {}\nQueued events: {} (queue full {} times)",
+            self.sent_xdcs,
+            self.sent_xdc_bytes / 1024,
+            self.errors,
+            self.queued_events,
+            self.queue_full
+        );
+        status.push_str("Requests:");
+        if self.requests.is_empty() {
+            status.push_str(" none");
+        }
+        for (request, count) in &self.requests {
+            let _ = write!(status, "\n  {request}: {count}");
+        }
+        status.push('\n');
+        status
+    }
+}
+
+/// Formats seconds as e.g. `2h 5m 3s`.
+fn format_duration(seconds: i64) -> String {
+    let seconds = seconds.max(0);
+    match (seconds / 3600, seconds % 3600 / 60, seconds % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m {s}s"),
+        (h, m, s) => format!("{h}h {m}m {s}s"),
+    }
+}
+
+/// Serves the metrics on `addr` until `shutdown` is set or an error occurs.
+pub async fn serve(
+    state: Arc<State>,
+    addr: SocketAddr,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let make_service = make_service_fn(move |_conn| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(handle(&state, req).await) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr)
+        .with_context(|| format!("Failed to listen on {addr}"))?
+        .http1_header_read_timeout(HEADER_READ_TIMEOUT)
+        .serve(make_service)
+        .with_graceful_shutdown(async move {
+            shutdown.changed().await.ok();
+        });
+    info!("Serving metrics on {addr}");
+    server.await?;
+    Ok(())
+}
+
+async fn handle(state: &State, req: Request<Body>) -> Response<Body> {
+    let (status, content_type, body) = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => (
+            StatusCode::OK,
+            "text/plain; version=0.0.4",
+            state.metrics.snapshot().to_prometheus(),
+        ),
+        (&Method::GET, "/status") => match serde_json::to_string(&state.metrics.snapshot()) {
+            Ok(json) => (StatusCode::OK, "application/json", json),
+            Err(e) => {
+                warn!("Failed to serialize status: {e}");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "text/plain",
+                    "Internal error\n".to_string(),
+                )
+            }
+        },
+        (&Method::GET, "/health") => match check_health(state).await {
+            Ok(_) => (StatusCode::OK, "text/plain", "ok\n".to_string()),
+            Err(e) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "text/plain",
+                format!("{e:#}\n"),
+            ),
+        },
+        _ => (
+            StatusCode::NOT_FOUND,
+            "text/plain",
+            "Not found\n".to_string(),
+        ),
+    };
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+    response
+}
+
+/// Checks that the bot is connected and its database is usable.
+/// Returns the last serial of the app index.
+pub async fn check_health(state: &State) -> Result<i32> {
+    if !state.metrics.snapshot().is_connected() {
+        bail!("Not connected");
+    }
+    db::get_last_serial(&mut *state.db.acquire().await?)
+        .await
+        .context("Database not available")
+}
+
+/// Fetches the metrics of the bot running with the metrics served on `addr`.
+pub async fn fetch_status(addr: SocketAddr) -> Result<Snapshot> {
+    reqwest::get(format!("http://{addr}/status"))
+        .await
+        .with_context(|| format!("Failed to connect to the bot on {addr}, is it running?"))?
+        .error_for_status()?
+        .json()
+        .await
+        .context("Failed to parse status")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let metrics = Metrics::new();
+        metrics.set_connectivity(4000);
+        metrics.record_request("Update");
+        metrics.record_request("Update");
+        metrics.record_request("Download");
+        metrics.record_sent_xdc(2048);
+        metrics.record_error();
+        metrics.record_enqueued();
+        metrics.record_enqueued();
+        metrics.record_dequeued();
+        metrics.record_queue_full();
+
+        let snapshot = metrics.snapshot();
+        assert!(snapshot.is_connected());
+        let text = snapshot.to_prometheus();
+        assert!(text.contains("# TYPE xdcstore_requests_total counter\n"));
+        assert!(text.contains("xdcstore_requests_total{request=\"Update\"} 2\n"));
+        assert!(text.contains("xdcstore_requests_total{request=\"Download\"} 1\n"));
+        assert!(text.contains("xdcstore_sent_xdc_bytes_total 2048\n"));
+        assert!(text.contains("xdcstore_errors_total 1\n"));
+        assert!(text.contains("xdcstore_queued_events 1\n"));
+        assert!(text.contains("xdcstore_queue_full_total 1\n"));
+
+        let status = snapshot.to_status(snapshot.started + 3725);
+        assert!(status.contains("Connectivity: connected\n"));
+        assert!(status.contains("Uptime: 1h 2m 5s\n"));
+        assert!(status.contains("Last event: none\n"));
+        assert!(status.contains("  Update: 2"));
+    }
+}
@@ -0,0 +1,184 @@
+//! Update notifications for subscribed contacts and users of outdated app versions.
+//!
+//! Apps are imported by a separate CLI process, so the running bot periodically checks
+//! for app versions with a serial greater than the last notified one.
+//! Critical updates are sent to every contact with an older version installed,
+//! other updates only to subscribers.
+//! The notifications are sent as jobs, so they are retried if sending fails.
+
+use anyhow::Result;
+use deltachat::{
+    chat::{self, ChatId},
+    contact::ContactId,
+    context::Context,
+};
+use log::{info, warn};
+use sqlx::SqliteConnection;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::watch;
+
+use crate::{
+    bot::State,
+    db,
+    jobs::{self, Job},
+    messages::{catalog, fill},
+    utils::send_app_xdc,
+};
+
+/// Periodically notifies subscribers about updated apps until `shutdown` is set.
+pub async fn notification_loop(
+    context: Context,
+    state: Arc<State>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let interval = Duration::from_secs(state.settings.notifications.interval);
+    while !*shutdown.borrow() {
+        if let Err(e) = notify_subscribers(&state).await {
+            warn!("Failed to notify subscribers: {e:#}");
+        }
+        tokio::select! {
+            _ = shutdown.changed() => break,
+            _ = tokio::time::sleep(interval) => {}
+        }
+    }
+}
+
+/// Queues notifications for the subscribers of all apps updated since the last check
+/// and critical update notifications for users of older versions.
+/// The notifications are queued in one transaction with the new notified serial,
+/// so they are neither lost nor queued twice if the bot stops in between.
+/// Apps which got a new serial without a new version, e.g. when unhidden, are only notified
+/// about again if a newer version was marked critical, see [db::NotifiedVersions].
+pub async fn notify_subscribers(state: &State) -> Result<()> {
+    let mut trans = state.db.begin().await?;
+    let conn = &mut *trans;
+    let notified_serial = db::get_notified_serial(conn).await?;
+    let last_serial = u32::try_from(db::get_last_serial(conn).await?)?;
+    if last_serial <= notified_serial {
+        return Ok(());
+    }
+
+    let mut queued = 0;
+    for app_info in db::get_active_app_infos_since(conn, notified_serial).await? {
+        if !db::app_has_older_version(conn, &app_info.app_id, app_info.version).await? {
+            continue;
+        }
+        let app_id = &app_info.app_id;
+        let notified_versions = db::get_notified_versions(conn, app_id).await?;
+        let critical_version = db::get_critical_version(conn, app_id)
+            .await?
+            .unwrap_or_default()
+            .max(notified_versions.critical_version);
+        let notified = if critical_version > notified_versions.critical_version {
+            queue_critical_updates(conn, app_id, critical_version).await?
+        } else {
+            vec![]
+        };
+        queued += notified.len();
+
+        if app_info.version > notified_versions.version {
+            let subscribers: Vec<_> = db::get_subscribers(conn, app_id)
+                .await?
+                .into_iter()
+                .filter(|contact_id| !notified.contains(contact_id))
+                .collect();
+            info!(
+                "Notifying {} subscribers about update of {app_id}",
+                subscribers.len(),
+            );
+            for contact_id in subscribers {
+                let job = Job::Notification {
+                    contact_id: contact_id.to_u32(),
+                    app_id: app_id.clone(),
+                };
+                jobs::add(conn, &job).await?;
+                queued += 1;
+            }
+        }
+
+        let versions = db::NotifiedVersions {
+            version: app_info.version.max(notified_versions.version),
+            critical_version,
+        };
+        db::set_notified_versions(conn, app_id, &versions).await?;
+    }
+
+    db::set_notified_serial(conn, last_serial).await?;
+    trans.commit().await?;
+    if queued > 0 {
+        state.new_jobs.notify_one();
+    }
+    Ok(())
+}
+
+/// Queues critical update notifications for all contacts with a version
+/// older than the critical `version` installed and returns these contacts.
+/// [State::new_jobs] has to be notified afterwards.
+async fn queue_critical_updates(
+    conn: &mut SqliteConnection,
+    app_id: &str,
+    version: u32,
+) -> Result<Vec<ContactId>> {
+    let contacts = db::get_contacts_with_older_version(conn, app_id, version).await?;
+    info!(
+        "Notifying {} contacts about critical update of {app_id}",
+        contacts.len(),
+    );
+    for contact_id in &contacts {
+        let job = Job::CriticalUpdate {
+            contact_id: contact_id.to_u32(),
+            app_id: app_id.to_string(),
+        };
+        jobs::add(conn, &job).await?;
+    }
+    Ok(contacts)
+}
+
+/// Sends an update notification about the newest version of an app
+/// to the 1:1 chat with a contact. Hidden apps are skipped.
+/// Critical update notifications are skipped if the contact updated in the meantime.
+pub async fn notify(
+    context: &Context,
+    state: &State,
+    contact_id: ContactId,
+    app_id: &str,
+    critical: bool,
+) -> Result<()> {
+    let mut conn = state.db.acquire().await?;
+    let mut app_info = match db::get_app_info_for_app_id(&mut conn, app_id).await {
+        Ok(app_info) => app_info,
+        Err(sqlx::Error::RowNotFound) => {
+            info!("Not notifying about {app_id}, it is no longer available");
+            return Ok(());
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if critical {
+        let installed = db::get_installed_version(&mut conn, contact_id, app_id).await?;
+        if installed.map_or(true, |version| version >= app_info.version) {
+            info!("Not notifying contact {contact_id} about {app_id}, it is up to date");
+            return Ok(());
+        }
+    }
+    let locale = db::get_contact_locale(&mut conn, contact_id).await?;
+    let locale = locale.as_deref();
+    if let Some(locale) = locale {
+        app_info.translations = db::get_app_translations(&mut conn, app_info.id).await?;
+        app_info.localize(locale);
+    }
+    drop(conn);
+    let text = if critical {
+        catalog(locale).critical_update
+    } else {
+        catalog(locale).app_updated
+    };
+    let text = fill(text, &[&app_info.name, &app_info.version.to_string()]);
+
+    let chat_id = ChatId::create_for_contact(context, contact_id).await?;
+    if state.settings.notifications.send_xdc {
+        send_app_xdc(context, state, chat_id, &app_info, Some(text)).await?;
+    } else {
+        chat::send_text_msg(context, chat_id, text).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,39 @@
+//! Per-chat request rate limiting.
+
+use deltachat::chat::ChatId;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Counts requests per chat in fixed one minute windows.
+pub struct RateLimiter {
+    requests_per_minute: u32,
+    windows: Mutex<HashMap<ChatId, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    /// Creates a new limiter, a limit of `0` allows every request.
+    pub fn new(requests_per_minute: u32) -> Self {
+        Self {
+            requests_per_minute,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Records a request and returns whether it is within the limit.
+    pub fn check(&self, chat_id: ChatId) -> bool {
+        if self.requests_per_minute == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        windows.retain(|_, (start, _)| now.duration_since(*start) < WINDOW);
+        let (_, count) = windows.entry(chat_id).or_insert((now, 0));
+        *count += 1;
+        *count <= self.requests_per_minute
+    }
+}
@@ -0,0 +1,375 @@
+//! Text commands available in the 1:1 store chats and the genesis group.
+use super::{store, AppInfo, ChatType, Review};
+use crate::{
+    bot::State,
+    db::{self, RecordId, Report},
+    messages::{catalog, fill, normalize_locale, Catalog},
+    utils::{
+        format_download_stats, get_chat_contact, get_chat_locale, record_download, send_app_xdc,
+    },
+    STATS_RECENT_DAYS, VERSION,
+};
+use anyhow::Context as _;
+use deltachat::{
+    chat::{self, ChatId},
+    context::Context,
+};
+use sqlx::SqliteConnection;
+use time::{Duration, OffsetDateTime};
+
+/// Number of apps listed by `/new`.
+const RECENT_APPS: u32 = 5;
+/// Number of reviews listed by `/reviews`.
+const MODERATED_REVIEWS: u32 = 50;
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Version,
+    Search(String),
+    Info(String),
+    Get(String),
+    New,
+    Subscribe(String),
+    Unsubscribe(String),
+    Language(String),
+    Stats,
+    Reviews(String),
+    RemoveReview(RecordId),
+    Reports,
+    ResolveReport(RecordId),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    /// The command does not exist.
+    Unknown(String),
+    /// A required argument is missing, contains the usage.
+    MissingArgument(&'static str),
+}
+
+impl Command {
+    /// Parses a chat message, returns `None` if it is not a command.
+    pub fn parse(text: &str) -> Option<Result<Self, ParseError>> {
+        let command = text.trim().strip_prefix('/')?;
+        let (name, arg) = match command.split_once(char::is_whitespace) {
+            Some((name, arg)) => (name, arg.trim()),
+            None => (command, ""),
+        };
+        let with_arg = |command: fn(String) -> Command, usage: &'static str| {
+            if arg.is_empty() {
+                Err(ParseError::MissingArgument(usage))
+            } else {
+                Ok(command(arg.to_string()))
+            }
+        };
+
+        Some(match name.to_lowercase().as_str() {
+            "help" => Ok(Command::Help),
+            "version" => Ok(Command::Version),
+            "search" => with_arg(Command::Search, "/search <term>"),
+            "info" => with_arg(Command::Info, "/info <app_id>"),
+            "get" => with_arg(Command::Get, "/get <app_id>"),
+            "new" => Ok(Command::New),
+            "subscribe" => with_arg(Command::Subscribe, "/subscribe <app_id>"),
+            "unsubscribe" => with_arg(Command::Unsubscribe, "/unsubscribe <app_id>"),
+            "language" => with_arg(Command::Language, "/language <code>"),
+            "stats" => Ok(Command::Stats),
+            "reviews" => with_arg(Command::Reviews, "/reviews <app_id>"),
+            "removereview" => arg
+                .parse()
+                .map(Command::RemoveReview)
+                .map_err(|_| ParseError::MissingArgument("/removereview <review_id>")),
+            "reports" => Ok(Command::Reports),
+            "resolvereport" => arg
+                .parse()
+                .map(Command::ResolveReport)
+                .map_err(|_| ParseError::MissingArgument("/resolvereport <report_id>")),
+            _ => Err(ParseError::Unknown(format!("/{name}"))),
+        })
+    }
+
+    /// Returns whether the command is only available in the genesis group.
+    fn genesis_only(&self) -> bool {
+        matches!(
+            self,
+            Command::Stats
+                | Command::Reviews(_)
+                | Command::RemoveReview(_)
+                | Command::Reports
+                | Command::ResolveReport(_)
+        )
+    }
+}
+
+/// Handles a chat message if it is a command.
+/// Returns whether the message was a command.
+pub async fn handle_command(
+    context: &Context,
+    state: &State,
+    chat_id: ChatId,
+    chat_type: ChatType,
+    text: &str,
+) -> anyhow::Result<bool> {
+    let Some(command) = Command::parse(text) else {
+        return Ok(false);
+    };
+    if chat_type == ChatType::Store && !store::is_allowed(context, state, chat_id).await? {
+        return Ok(false);
+    }
+
+    let conn = &mut *state.db.acquire().await?;
+    let locale = match chat_type {
+        ChatType::Store => get_chat_locale(context, conn, chat_id).await?,
+        ChatType::Genesis => None,
+    };
+    let texts = catalog(locale.as_deref());
+
+    let command = command.and_then(|command| {
+        if command.genesis_only() && chat_type != ChatType::Genesis {
+            let name = text.split_whitespace().next().unwrap_or_default();
+            Err(ParseError::Unknown(name.to_string()))
+        } else {
+            Ok(command)
+        }
+    });
+
+    let reply = match command {
+        Err(ParseError::Unknown(name)) => fill(texts.unknown_command, &[&name]),
+        Err(ParseError::MissingArgument(usage)) => fill(texts.usage, &[usage]),
+        Ok(Command::Help) => match chat_type {
+            ChatType::Store => texts.help.to_string(),
+            ChatType::Genesis => format!("{}\n\n{}", texts.help, texts.help_genesis),
+        },
+        Ok(Command::Version) => VERSION.to_string(),
+        Ok(Command::Search(term)) => {
+            let app_infos = db::search_app_infos(conn, &term).await?;
+            if app_infos.is_empty() {
+                fill(texts.search_no_results, &[&term])
+            } else {
+                let app_infos = localized(conn, app_infos, locale.as_deref()).await?;
+                format!(
+                    "{}\n{}",
+                    fill(texts.search_results, &[&term]),
+                    app_list(&app_infos)
+                )
+            }
+        }
+        Ok(Command::New) => {
+            let app_infos = db::get_recent_app_infos(conn, RECENT_APPS).await?;
+            if app_infos.is_empty() {
+                texts.no_apps.to_string()
+            } else {
+                let app_infos = localized(conn, app_infos, locale.as_deref()).await?;
+                format!("{}\n{}", texts.new_apps, app_list(&app_infos))
+            }
+        }
+        Ok(Command::Info(app_id)) => match get_app_info(conn, &app_id).await? {
+            Some(app_info) => {
+                let app_info = localized(conn, vec![app_info], locale.as_deref())
+                    .await?
+                    .pop()
+                    .context("app info vanished while localizing")?;
+                app_details(texts, &app_info)
+            }
+            None => fill(texts.app_not_found, &[&app_id]),
+        },
+        Ok(Command::Get(app_id)) => match get_app_info(conn, &app_id).await? {
+            Some(app_info) => {
+                send_app_xdc(context, state, chat_id, &app_info, None).await?;
+                if let Some(contact_id) = get_chat_contact(context, chat_id).await? {
+                    if chat_type == ChatType::Store {
+                        // The xdc is sent to the chat, so no device of the contact is known.
+                        record_download(conn, &app_info, contact_id, "").await?;
+                    }
+                }
+                return Ok(true);
+            }
+            None => fill(texts.app_not_found, &[&app_id]),
+        },
+        Ok(Command::Subscribe(app_id)) => match get_chat_contact(context, chat_id).await? {
+            Some(contact_id) if chat_type == ChatType::Store => {
+                if db::visible_app_exists(conn, &app_id).await? {
+                    db::add_subscription(conn, contact_id, &app_id).await?;
+                    fill(texts.subscribed, &[&app_id])
+                } else {
+                    fill(texts.app_not_found, &[&app_id])
+                }
+            }
+            _ => fill(texts.unknown_command, &["/subscribe"]),
+        },
+        Ok(Command::Unsubscribe(app_id)) => match get_chat_contact(context, chat_id).await? {
+            Some(contact_id) if chat_type == ChatType::Store => {
+                db::remove_subscription(conn, contact_id, &app_id).await?;
+                fill(texts.unsubscribed, &[&app_id])
+            }
+            _ => fill(texts.unknown_command, &["/unsubscribe"]),
+        },
+        Ok(Command::Language(language)) => {
+            let locale = normalize_locale(&language);
+            match get_chat_contact(context, chat_id).await? {
+                Some(contact_id) if chat_type == ChatType::Store => {
+                    db::set_contact_locale(conn, contact_id, &locale).await?;
+                    fill(catalog(Some(&locale)).language_set, &[&locale])
+                }
+                _ => fill(texts.unknown_command, &["/language"]),
+            }
+        }
+        Ok(Command::Stats) => {
+            let since = OffsetDateTime::now_utc() - Duration::days(STATS_RECENT_DAYS);
+            let stats = db::get_download_stats(conn, since.unix_timestamp()).await?;
+            if stats.is_empty() {
+                texts.no_downloads.to_string()
+            } else {
+                format_download_stats(&stats, STATS_RECENT_DAYS)
+            }
+        }
+        Ok(Command::Reviews(app_id)) => {
+            let reviews = db::get_reviews(conn, &app_id, MODERATED_REVIEWS).await?;
+            if reviews.is_empty() {
+                fill(texts.no_reviews, &[&app_id])
+            } else {
+                review_list(&reviews)
+            }
+        }
+        Ok(Command::RemoveReview(id)) => {
+            if db::remove_review(conn, id).await? {
+                fill(texts.review_removed, &[&id.to_string()])
+            } else {
+                fill(texts.review_not_found, &[&id.to_string()])
+            }
+        }
+        Ok(Command::Reports) => {
+            let reports = db::get_open_reports(conn).await?;
+            if reports.is_empty() {
+                texts.no_reports.to_string()
+            } else {
+                report_list(&reports)
+            }
+        }
+        Ok(Command::ResolveReport(id)) => {
+            if db::resolve_report(conn, id).await? {
+                fill(texts.report_resolved, &[&id.to_string()])
+            } else {
+                fill(texts.report_not_found, &[&id.to_string()])
+            }
+        }
+    };
+
+    chat::send_text_msg(context, chat_id, reply).await?;
+    Ok(true)
+}
+
+/// Returns the newest version of an app, if it exists.
+async fn get_app_info(
+    conn: &mut SqliteConnection,
+    app_id: &str,
+) -> anyhow::Result<Option<AppInfo>> {
+    match db::get_app_info_for_app_id(conn, app_id).await {
+        Ok(app_info) => Ok(Some(app_info)),
+        Err(sqlx::Error::RowNotFound) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn localized(
+    conn: &mut SqliteConnection,
+    mut app_infos: Vec<AppInfo>,
+    locale: Option<&str>,
+) -> anyhow::Result<Vec<AppInfo>> {
+    if let Some(locale) = locale {
+        db::load_translations(conn, &mut app_infos).await?;
+        for app_info in &mut app_infos {
+            app_info.localize(locale);
+        }
+    }
+    Ok(app_infos)
+}
+
+fn app_list(app_infos: &[AppInfo]) -> String {
+    app_infos
+        .iter()
+        .map(|app_info| format!("• {} ({})", app_info.name, app_info.app_id))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn app_details(texts: &Catalog, app_info: &AppInfo) -> String {
+    format!(
+        "{} ({})\n{}\n\n{}",
+        app_info.name,
+        app_info.app_id,
+        app_info.description,
+        fill(
+            texts.app_details,
+            &[
+                &app_info.version.to_string(),
+                &(app_info.size / 1024).to_string(),
+                app_info.source_code_url.as_deref().unwrap_or("-"),
+            ]
+        )
+    )
+}
+
+fn review_list(reviews: &[Review]) -> String {
+    reviews
+        .iter()
+        .map(|review| {
+            format!(
+                "#{} v{} {}{} {}",
+                review.id,
+                review.version,
+                "★".repeat(review.stars.into()),
+                "☆".repeat(5_usize.saturating_sub(review.stars.into())),
+                review.comment.as_deref().unwrap_or_default()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn report_list(reports: &[Report]) -> String {
+    reports
+        .iter()
+        .map(|report| {
+            format!(
+                "#{} {} v{}: {}",
+                report.id, report.app_id, report.version, report.reason
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_commands() {
+        assert_eq!(Command::parse("hi!"), None);
+        assert_eq!(Command::parse("/help"), Some(Ok(Command::Help)));
+        assert_eq!(
+            Command::parse(" /Search  hex tris "),
+            Some(Ok(Command::Search("hex tris".to_string())))
+        );
+        assert_eq!(
+            Command::parse("/get"),
+            Some(Err(ParseError::MissingArgument("/get <app_id>")))
+        );
+        assert_eq!(
+            Command::parse("/foo bar"),
+            Some(Err(ParseError::Unknown("/foo".to_string())))
+        );
+        assert_eq!(
+            Command::parse("/removereview 12"),
+            Some(Ok(Command::RemoveReview(12)))
+        );
+        assert_eq!(
+            Command::parse("/removereview abc"),
+            Some(Err(ParseError::MissingArgument(
+                "/removereview <review_id>"
+            )))
+        );
+    }
+}
@@ -1,17 +1,23 @@
 //! Handlers for the different messages the bot receives
 use crate::{
     db::RecordId,
+    messages::{language, normalize_locale},
     utils::{get_webxdc_manifest, read_vec},
 };
+use anyhow::Context as _;
 use async_zip::tokio::read::fs::ZipFileReader;
 use base64::encode;
 use serde::{Deserialize, Serialize};
 use sqlx::{Decode, FromRow, Type};
-use std::path::{Path, PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 use tokio::fs::File;
 use ts_rs::TS;
 
+pub mod commands;
 pub mod genesis;
 pub mod store;
 
@@ -37,6 +43,25 @@ pub struct WexbdcManifest {
 
     /// Date displayed in the store.
     pub date: String,
+
+    /// Localized names and descriptions keyed by locale, e.g. `[translations.de]`.
+    #[serde(default)]
+    pub translations: BTreeMap<String, ManifestTranslation>,
+}
+
+#[derive(Deserialize)]
+pub struct ManifestTranslation {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Localized name and description of an app.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct AppTranslation {
+    /// Normalized locale, see [normalize_locale].
+    pub locale: String,
+    pub name: String,
+    pub description: String,
 }
 
 #[derive(TS, Deserialize, Serialize, Clone, Debug, Default, PartialEq, FromRow, Decode)]
@@ -54,12 +79,91 @@ pub struct AppInfo {
     pub source_code_url: Option<String>, // manifest
     pub image: String,  // webxdc
     pub description: String, // submit
+    /// Path of the xdc relative to the blob root, see [AppInfo::blob_key].
     #[serde(skip)]
     pub xdc_blob_path: PathBuf, // bot
     pub size: i64,      //bot
+    #[serde(skip)]
+    pub translations: Vec<AppTranslation>, // manifest
+    /// Whether the version fixes a critical, e.g. security, problem of older versions.
+    #[serde(default)]
+    pub critical: bool, // bot
+    /// Total number of downloads, only sent if enabled in the settings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub downloads: Option<u32>, // bot
+    /// Average rating of all versions, only sent if the app has been rated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub rating: Option<Rating>, // bot
+}
+
+/// Aggregated star ratings of an app.
+#[derive(TS, Deserialize, Serialize, Clone, Copy, Debug, Default, PartialEq, FromRow)]
+#[ts(export)]
+#[ts(export_to = "frontend/src/bindings/")]
+pub struct Rating {
+    /// Average number of stars.
+    pub average: f32,
+    /// Number of reviews.
+    pub count: u32,
+}
+
+/// Download count and rating of an app, which change without the app getting a new serial.
+#[derive(TS, Serialize, Clone, Copy, Debug, Default, PartialEq)]
+#[ts(export)]
+#[ts(export_to = "frontend/src/bindings/")]
+pub struct AppStats {
+    /// Total number of downloads, only sent if enabled in the settings.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub downloads: Option<u32>,
+    /// Average rating of all versions, only sent if the app has been rated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub rating: Option<Rating>,
+}
+
+/// A user review of one app version.
+#[derive(TS, Serialize, Clone, Debug, Default, PartialEq, FromRow)]
+#[ts(export)]
+#[ts(export_to = "frontend/src/bindings/")]
+pub struct Review {
+    pub id: RecordId,
+    pub app_id: String,
+    /// Version of the app the review was written for.
+    pub version: u32,
+    /// Number of stars from 1 to 5.
+    pub stars: u8,
+    pub comment: Option<String>,
+    /// Unix timestamp of the last change.
+    pub date: i64,
 }
 
 impl AppInfo {
+    /// Replaces name and description with the translation for `locale`.
+    /// Falls back to a translation for the language only, e.g. `de` for `de-at`.
+    pub fn localize(&mut self, locale: &str) {
+        let locale = normalize_locale(locale);
+        let language = language(&locale);
+        let translation = self
+            .translations
+            .iter()
+            .find(|t| t.locale == locale)
+            .or_else(|| self.translations.iter().find(|t| t.locale == language));
+        if let Some(translation) = translation {
+            self.name = translation.name.clone();
+            self.description = translation.description.clone();
+        }
+    }
+
+    /// Returns the key of the xdc in the [crate::blob_store::BlobStore].
+    pub fn blob_key(&self) -> anyhow::Result<&str> {
+        self.xdc_blob_path
+            .to_str()
+            .context("Can't convert blob path to str")
+    }
+
     /// Create appinfo from webxdc file.
     pub async fn from_xdc(file: &Path) -> anyhow::Result<Self> {
         let size = i64::try_from(File::open(&file).await?.metadata().await?.len())?;
@@ -86,6 +190,18 @@ impl AppInfo {
             Err(anyhow::anyhow!("Could not find image"))
         };
 
+        let translations = manifest
+            .translations
+            .into_iter()
+            .map(|(locale, translation)| AppTranslation {
+                locale: normalize_locale(&locale),
+                name: translation.name.unwrap_or_else(|| manifest.name.clone()),
+                description: translation
+                    .description
+                    .unwrap_or_else(|| manifest.description.clone()),
+            })
+            .collect();
+
         Ok(Self {
             size,
             date: OffsetDateTime::parse(&manifest.date, &Rfc3339)?.unix_timestamp(),
@@ -97,6 +213,10 @@ impl AppInfo {
             image: image?,
             description: manifest.description,
             xdc_blob_path: file.to_path_buf(),
+            translations,
+            critical: false,
+            downloads: None,
+            rating: None,
             id: 0, // This will be updated by the db on insert
         })
     }
@@ -109,20 +229,160 @@ pub enum ChatType {
     Genesis,
 }
 
+/// How updates of installed apps are delivered to a user.
+#[derive(TS, Serialize, Deserialize, Type, Clone, Copy, Debug, Default, PartialEq)]
+#[ts(export)]
+#[ts(export_to = "frontend/src/bindings/")]
+pub enum UpdatePolicy {
+    /// New versions are sent without asking.
+    #[default]
+    Auto,
+    /// New versions are only offered and have to be downloaded by the user.
+    Ask,
+    /// Only critical updates are sent without asking, others are offered.
+    CriticalOnly,
+}
+
 /// A generic webxdc update
 #[derive(Deserialize)]
 pub struct WebxdcStatusUpdate<T> {
     pub payload: T,
 }
 
+/// Payload of an update sent by the bot.
+/// Updates are echoed back to the sender, the marker lets the bot skip its own ones.
+#[derive(Serialize)]
+pub struct BotPayload<T> {
+    from_bot: bool,
+    #[serde(flatten)]
+    content: T,
+}
+
+impl<T> BotPayload<T> {
+    pub fn new(content: T) -> Self {
+        Self {
+            from_bot: true,
+            content,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct BotMarker {
+    #[serde(default)]
+    from_bot: bool,
+}
+
+/// Returns whether a webxdc update was sent by the bot itself, see [BotPayload].
+pub fn is_bot_update(update: &str) -> bool {
+    serde_json::from_str::<WebxdcStatusUpdate<BotMarker>>(update)
+        .map_or(false, |update| update.payload.from_bot)
+}
+
+/// Payload of a request or response with an optional ID to match them.
+/// The ID is chosen by the frontend and echoed in all responses to the request.
+/// Frontends which don't send an ID get responses without one.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Envelope<T> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(flatten)]
+    pub content: T,
+}
+
+impl<T> Envelope<T> {
+    pub fn new(request_id: Option<&str>, content: T) -> Self {
+        Self {
+            request_id: request_id.map(str::to_string),
+            content,
+        }
+    }
+}
+
+/// Returns the request ID of a webxdc update, even if it is no valid request.
+pub fn get_request_id(update: &str) -> Option<String> {
+    serde_json::from_str::<WebxdcStatusUpdate<serde_json::Value>>(update)
+        .ok()?
+        .payload
+        .get("request_id")?
+        .as_str()
+        .map(str::to_string)
+}
+
+/// Version of the protocol between the store.xdc and the bot, increased on incompatible changes.
+/// Frontends which don't send a [GeneralFrontendRequest::Hello] are assumed to speak version 1.
+pub const PROTOCOL_VERSION: u32 = 2;
+/// Protocol version of frontends without handshake.
+pub const LEGACY_PROTOCOL_VERSION: u32 = 1;
+/// Oldest protocol version which is still served, older frontends are told to update urgently.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// Optional parts of the protocol the bot supports.
+#[derive(Serialize, Deserialize, TS, Clone, Copy, Debug, PartialEq)]
+#[ts(export)]
+#[ts(export_to = "frontend/src/bindings/")]
+pub enum Feature {
+    /// Request IDs are echoed in responses, see [Envelope].
+    RequestIds,
+    /// Failed requests are answered with a [store::StoreError].
+    TypedErrors,
+    Reviews,
+    Reports,
+    Subscriptions,
+    /// App infos are localized to the language of the frontend.
+    Translations,
+}
+
+/// All features supported by this bot.
+pub const FEATURES: &[Feature] = &[
+    Feature::RequestIds,
+    Feature::TypedErrors,
+    Feature::Reviews,
+    Feature::Reports,
+    Feature::Subscriptions,
+    Feature::Translations,
+];
+
+/// A newer version of the store.xdc.
+#[derive(Serialize, Deserialize, TS, Clone, Copy, Debug, PartialEq)]
+#[ts(export)]
+#[ts(export_to = "frontend/src/bindings/")]
+pub struct StoreXdcUpdate {
+    pub version: u32,
+    /// Whether the protocol version of the frontend is no longer supported.
+    /// Frontends may only offer optional updates without blocking the user.
+    pub critical: bool,
+}
+
+impl StoreXdcUpdate {
+    /// Returns the update for a frontend, if it is outdated.
+    pub fn check(version: u32, newest_version: u32, protocol_version: u32) -> Option<Self> {
+        (version < newest_version).then_some(Self {
+            version: newest_version,
+            critical: protocol_version < MIN_PROTOCOL_VERSION,
+        })
+    }
+}
+
 #[derive(Serialize, Deserialize, TS)]
 #[ts(export)]
 #[ts(export_to = "frontend/src/bindings/")]
 #[serde(tag = "type")]
 
 pub enum GeneralFrontendResponse {
-    Outdated { critical: bool, version: u32 },
+    Outdated {
+        critical: bool,
+        version: u32,
+    },
     UpdateSent,
+    /// Response to [GeneralFrontendRequest::Hello].
+    Welcome {
+        /// Protocol version of the bot.
+        protocol_version: u32,
+        features: Vec<Feature>,
+        /// Set if a newer store.xdc is available.
+        update: Option<StoreXdcUpdate>,
+    },
 }
 
 #[derive(Deserialize, TS)]
@@ -131,4 +391,49 @@ pub enum GeneralFrontendResponse {
 #[serde(tag = "type")]
 pub enum GeneralFrontendRequest {
     UpdateWebxdc,
+    /// Handshake sent by the frontend on start.
+    Hello {
+        protocol_version: u32,
+        /// Optional features supported by the frontend, unknown ones are ignored.
+        #[serde(default)]
+        capabilities: Vec<String>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bot_update() {
+        let payload = BotPayload::new(GeneralFrontendResponse::UpdateSent);
+        let update = serde_json::json!({ "payload": payload }).to_string();
+        assert_eq!(
+            update,
+            r#"{"payload":{"from_bot":true,"type":"UpdateSent"}}"#
+        );
+        assert!(is_bot_update(&update));
+        assert!(!is_bot_update(r#"{"payload":{"type":"UpdateWebxdc"}}"#));
+        assert!(!is_bot_update(r#"{"payload":"malformed"}"#));
+        assert!(!is_bot_update("not json"));
+    }
+
+    #[test]
+    fn test_store_xdc_update() {
+        assert_eq!(StoreXdcUpdate::check(3, 3, LEGACY_PROTOCOL_VERSION), None);
+        assert_eq!(
+            StoreXdcUpdate::check(2, 3, PROTOCOL_VERSION),
+            Some(StoreXdcUpdate {
+                version: 3,
+                critical: false
+            })
+        );
+        assert_eq!(
+            StoreXdcUpdate::check(2, 3, MIN_PROTOCOL_VERSION - 1),
+            Some(StoreXdcUpdate {
+                version: 3,
+                critical: true
+            })
+        );
+    }
 }
@@ -1,23 +1,39 @@
-use super::{AppInfo, WebxdcStatusUpdate};
+use super::{
+    get_request_id, AppInfo, AppStats, Envelope, Rating, Review, UpdatePolicy, WebxdcStatusUpdate,
+};
 use crate::{
     bot::State,
-    db,
-    messages::store_message,
-    utils::{send_newest_updates, send_update_payload_only, send_webxdc, Webxdc},
+    db::{self, Report},
+    jobs::{self, Job},
+    messages::{catalog, fill, normalize_locale},
+    settings::AccessMode,
+    utils::{
+        get_chat_contact, get_chat_locale, record_download, send_newest_updates, send_response,
+        send_store_xdc,
+    },
 };
-use anyhow::Context as _;
+use anyhow::{bail, Context as _};
 use base64::encode;
 use deltachat::{
     chat::{self, ChatId},
     constants,
+    contact::{Contact, ContactId, VerifiedStatus},
     context::Context,
     message::MsgId,
 };
-use log::{info, warn};
+use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::{collections::BTreeMap, sync::Arc};
+use time::OffsetDateTime;
 use ts_rs::TS;
 
+/// Maximum number of characters of a review comment or report reason.
+const MAX_COMMENT_LENGTH: usize = 1000;
+/// Maximum number of reviews sent for `GetReviews`.
+const MAX_REVIEWS: u32 = 100;
+/// Maximum length of a device ID.
+const MAX_DEVICE_LENGTH: usize = 64;
+
 #[derive(Deserialize, TS)]
 #[ts(export)]
 #[ts(export_to = "frontend/src/bindings/")]
@@ -25,16 +41,119 @@ enum StoreRequest {
     Update {
         /// Requested update sequence number.
         serial: u32,
-        /// Listof apps selected for caching.
+        /// `app_id`s and versions of the apps cached by the frontend.
+        /// They replace the stored apps of the device, if omitted the stored apps are used instead.
+        #[serde(default)]
+        apps: Option<Vec<(String, u32)>>,
+        /// Random ID of the device the frontend runs on, chosen by the frontend.
+        /// Installed apps are stored per device, see [db::set_installed_apps].
+        #[serde(default)]
+        device: Option<String>,
+        /// Language of the frontend, e.g. `de-AT`.
+        /// Stored as the user's locale and used to localize the app infos.
         #[serde(default)]
-        apps: Vec<(String, u32)>,
+        language: Option<String>,
     },
     Download {
         /// ID of the requested application.
         app_id: String,
+        /// Device the app is installed on, see `Update`.
+        #[serde(default)]
+        device: Option<String>,
+    },
+    Subscribe {
+        /// ID of the application to receive update notifications for.
+        app_id: String,
+    },
+    Unsubscribe {
+        /// ID of the application to stop update notifications for.
+        app_id: String,
+    },
+    Rate {
+        /// ID of the rated application, the newest version is rated.
+        app_id: String,
+        /// Number of stars from 1 to 5.
+        stars: u8,
+        /// Optional review text.
+        comment: Option<String>,
+    },
+    GetReviews {
+        /// ID of the application to get the reviews for.
+        app_id: String,
+    },
+    Report {
+        /// ID of the reported application, the newest version is reported.
+        app_id: String,
+        /// Description of the problem.
+        reason: String,
+    },
+    SetUpdatePolicy {
+        /// How updates of installed apps are delivered to the user.
+        policy: UpdatePolicy,
     },
 }
 
+impl StoreRequest {
+    /// Name of the request type used in the metrics.
+    fn name(&self) -> &'static str {
+        match self {
+            StoreRequest::Update { .. } => "Update",
+            StoreRequest::Download { .. } => "Download",
+            StoreRequest::Subscribe { .. } => "Subscribe",
+            StoreRequest::Unsubscribe { .. } => "Unsubscribe",
+            StoreRequest::Rate { .. } => "Rate",
+            StoreRequest::GetReviews { .. } => "GetReviews",
+            StoreRequest::Report { .. } => "Report",
+            StoreRequest::SetUpdatePolicy { .. } => "SetUpdatePolicy",
+        }
+    }
+}
+
+/// Reason why a request failed, sent to the frontend.
+#[derive(TS, Serialize, Clone, Debug, PartialEq, thiserror::Error)]
+#[ts(export)]
+#[ts(export_to = "frontend/src/bindings/")]
+#[serde(tag = "kind")]
+pub enum StoreError {
+    /// The requested app does not exist.
+    #[error("App not found")]
+    NotFound,
+    /// The xdc of the requested app version is no longer available.
+    #[error("App version is no longer available")]
+    VersionGone,
+    /// The chat sent too many requests, see [crate::rate_limit].
+    #[error("Too many requests")]
+    RateLimited,
+    /// The bot failed to handle the request.
+    #[error("Internal error")]
+    Internal,
+    /// The request could not be parsed or contains invalid values.
+    #[error("Malformed request: {reason}")]
+    Malformed { reason: String },
+}
+
+impl StoreError {
+    fn malformed(reason: impl Into<String>) -> Self {
+        StoreError::Malformed {
+            reason: reason.into(),
+        }
+    }
+}
+
+impl From<&anyhow::Error> for StoreError {
+    /// Keeps a [StoreError] in the error chain and maps missing rows to [StoreError::NotFound].
+    /// All other errors are internal, their details are only logged.
+    fn from(error: &anyhow::Error) -> Self {
+        if let Some(error) = error.downcast_ref::<StoreError>() {
+            return error.clone();
+        }
+        match error.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::RowNotFound) => StoreError::NotFound,
+            _ => StoreError::Internal,
+        }
+    }
+}
+
 #[derive(TS, Serialize)]
 #[ts(export)]
 #[ts(export_to = "frontend/src/bindings/")]
@@ -50,7 +169,7 @@ pub enum StoreResponse {
     },
     DownloadError {
         app_id: String,
-        error: String,
+        error: StoreError,
     },
     Update {
         /// List of new / updated app infos.
@@ -59,6 +178,50 @@ pub enum StoreResponse {
         /// `app_id`s of apps that will receive an update.
         /// The frontend can use these to set the state to updating.
         updating: Vec<String>,
+        /// `app_id`s of apps hidden since the requested serial, the frontend removes them.
+        removed: Vec<String>,
+        /// Current download counts and ratings of all apps, also of apps not in `app_infos`.
+        stats: BTreeMap<String, AppStats>,
+        /// `app_id`s and versions of the apps the user has installed on any device.
+        installed: Vec<(String, u32)>,
+        update_policy: UpdatePolicy,
+    },
+    /// Updates of installed apps which are not sent automatically, see [UpdatePolicy].
+    /// They can be downloaded with a `Download` request.
+    UpdatesAvailable {
+        app_ids: Vec<String>,
+    },
+    UpdatePolicy {
+        policy: UpdatePolicy,
+    },
+    Subscriptions {
+        /// `app_id`s of all apps the user receives update notifications for.
+        app_ids: Vec<String>,
+    },
+    RateOkay {
+        app_id: String,
+        /// New rating of the app including the user's review.
+        rating: Rating,
+    },
+    RateError {
+        app_id: String,
+        error: StoreError,
+    },
+    Reviews {
+        app_id: String,
+        /// Newest reviews first.
+        reviews: Vec<Review>,
+    },
+    ReportOkay {
+        app_id: String,
+    },
+    ReportError {
+        app_id: String,
+        error: StoreError,
+    },
+    /// Response to a request which failed and has no specific error response.
+    Error {
+        error: StoreError,
     },
 }
 
@@ -69,100 +232,559 @@ pub async fn handle_message(
 ) -> anyhow::Result<()> {
     let chat = chat::Chat::load_from_db(context, chat_id).await?;
     if let constants::Chattype::Single = chat.typ {
-        let msg = send_webxdc(
-            context,
-            &state,
-            chat_id,
-            Webxdc::Store,
-            Some(store_message()),
-        )
-        .await?;
-        send_newest_updates(context, msg, &mut *state.db.acquire().await?, 0, vec![]).await?;
+        if !is_allowed(context, &state, chat_id).await? {
+            let locale = get_chat_locale(context, &mut *state.db.acquire().await?, chat_id).await?;
+            let text = catalog(locale.as_deref()).invite_only;
+            chat::send_text_msg(context, chat_id, text.to_string()).await?;
+            return Ok(());
+        }
+
+        send_store_xdc(context, &state, chat_id).await?;
     }
     Ok(())
 }
 
+/// Returns whether the store may be used in a 1:1 chat according to the access mode.
+/// In invite-only mode the contact has to be verified by joining with the invite QR-code.
+pub async fn is_allowed(context: &Context, state: &State, chat_id: ChatId) -> anyhow::Result<bool> {
+    if state.settings.access.mode == AccessMode::Open {
+        return Ok(true);
+    }
+    let Some(contact_id) = get_chat_contact(context, chat_id).await? else {
+        return Ok(false);
+    };
+    let contact = Contact::get_by_id(context, contact_id).await?;
+    Ok(contact.is_verified(context).await? == VerifiedStatus::BidirectVerified)
+}
+
 pub async fn handle_status_update(
     context: &Context,
     state: Arc<State>,
+    chat_id: ChatId,
     msg_id: MsgId,
     update: String,
 ) -> anyhow::Result<()> {
-    if let Ok(req) = serde_json::from_str::<WebxdcStatusUpdate<StoreRequest>>(&update) {
-        match req.payload {
-            StoreRequest::Update { serial, apps } => {
-                info!("Handling store update request");
-
-                // Get all updating xdcs
-                let mut updating = vec![];
-                let conn = &mut *state.db.acquire().await?;
-                for (app_id, version) in apps {
-                    if db::maybe_get_greater_version(conn, &app_id, version).await? {
-                        updating.push(app_id);
+    let Envelope {
+        request_id,
+        content: request,
+    } = match parse_request(&update) {
+        Ok(request) => request,
+        Err(error) => {
+            state.metrics.record_error();
+            error!(
+                "Received malformed request in chat {chat_id}: {error}: {}",
+                &update.get(..100.min(update.len())).unwrap_or_default()
+            );
+            let request_id = get_request_id(&update);
+            let response = StoreResponse::Error { error };
+            return send_response(context, msg_id, request_id.as_deref(), response).await;
+        }
+    };
+
+    if !state.rate_limiter.check(chat_id) {
+        state.metrics.record_error();
+        warn!("Chat {chat_id} exceeded the request rate limit, rejecting request");
+        let response = StoreResponse::Error {
+            error: StoreError::RateLimited,
+        };
+        return send_response(context, msg_id, request_id.as_deref(), response).await;
+    }
+
+    state.metrics.record_request(request.name());
+    let request_id = request_id.as_deref();
+    if let Err(e) = handle_request(context, &state, chat_id, msg_id, request_id, request).await {
+        state.metrics.record_error();
+        warn!("Error while handling store request: {e:#}");
+        let error = StoreError::from(&e);
+        send_response(context, msg_id, request_id, StoreResponse::Error { error }).await?;
+    }
+    Ok(())
+}
+
+/// Parses a store request from a webxdc update.
+/// Updates sent by the bot itself have to be skipped before, see [super::is_bot_update].
+fn parse_request(update: &str) -> Result<Envelope<StoreRequest>, StoreError> {
+    serde_json::from_str::<WebxdcStatusUpdate<Envelope<StoreRequest>>>(update)
+        .map(|update| update.payload)
+        .map_err(|e| StoreError::malformed(e.to_string()))
+}
+
+/// Returns the device ID of a request, frontends without one share the empty ID.
+fn check_device(device: Option<String>) -> anyhow::Result<String> {
+    let device = device.unwrap_or_default();
+    if device.len() > MAX_DEVICE_LENGTH {
+        bail!(StoreError::malformed(format!(
+            "Device ID must not be longer than {MAX_DEVICE_LENGTH} bytes"
+        )));
+    }
+    Ok(device)
+}
+
+async fn handle_request(
+    context: &Context,
+    state: &State,
+    chat_id: ChatId,
+    msg_id: MsgId,
+    request_id: Option<&str>,
+    request: StoreRequest,
+) -> anyhow::Result<()> {
+    match request {
+        StoreRequest::Update {
+            serial,
+            apps,
+            device,
+            language,
+        } => {
+            info!("Handling store update request");
+            let device = check_device(device)?;
+            let contact_id = get_chat_contact(context, chat_id).await?;
+            let conn = &mut *state.db.acquire().await?;
+
+            if let (Some(language), Some(contact_id)) = (language, contact_id) {
+                db::set_contact_locale(conn, contact_id, &normalize_locale(&language)).await?;
+            }
+
+            let apps = match (apps, contact_id) {
+                (Some(apps), Some(contact_id)) => {
+                    let now = OffsetDateTime::now_utc().unix_timestamp();
+                    db::set_installed_apps(conn, contact_id, &device, &apps, now).await?;
+                    apps
+                }
+                (Some(apps), None) => apps,
+                (None, Some(contact_id)) => {
+                    db::get_installed_apps(conn, contact_id, Some(&device)).await?
+                }
+                (None, None) => vec![],
+            };
+
+            let policy = match contact_id {
+                Some(contact_id) => db::get_update_policy(conn, contact_id).await?,
+                None => UpdatePolicy::default(),
+            };
+
+            // Get all updating xdcs, the others are only offered
+            let mut updating = vec![];
+            let mut available = vec![];
+            for (app_id, version) in apps {
+                if !db::maybe_get_greater_visible_version(conn, &app_id, version).await? {
+                    continue;
+                }
+                let push = match policy {
+                    UpdatePolicy::Auto => true,
+                    UpdatePolicy::Ask => false,
+                    UpdatePolicy::CriticalOnly => {
+                        db::has_critical_update(conn, &app_id, version).await?
                     }
+                };
+                if push {
+                    updating.push(app_id);
+                } else {
+                    available.push(app_id);
                 }
+            }
 
-                info!("Updating multiple client apps: {:?}", updating);
+            info!("Updating multiple client apps: {:?}", updating);
 
-                send_newest_updates(
-                    context,
-                    msg_id,
-                    &mut *state.db.acquire().await?,
-                    serial,
-                    updating.clone(),
-                )
-                .await?;
+            send_newest_updates(
+                context,
+                state,
+                msg_id,
+                serial,
+                updating.clone(),
+                contact_id,
+                request_id,
+            )
+            .await?;
 
-                // Send updates
-                for app_id in &updating {
-                    let context = context.clone();
-                    let state = state.clone();
-                    let app_id = app_id.clone();
-                    let resp = handle_download(&state, app_id).await;
-                    send_update_payload_only(&context, msg_id, resp).await?;
-                }
+            if !available.is_empty() {
+                info!("Offering updates of {:?}", available);
+                let response = StoreResponse::UpdatesAvailable { app_ids: available };
+                send_response(context, msg_id, request_id, response).await?;
             }
-            StoreRequest::Download { app_id } => {
-                info!("Handling store download");
-                let resp = handle_download(&state, app_id).await;
-                send_update_payload_only(context, msg_id, resp).await?;
+
+            // Send updates
+            for app_id in updating {
+                let job = Job::Download {
+                    msg_id: msg_id.to_u32(),
+                    app_id,
+                    contact_id: contact_id.map(|contact_id| contact_id.to_u32()),
+                    device: Some(device.clone()),
+                    request_id: request_id.map(str::to_string),
+                };
+                jobs::enqueue(state, &job).await?;
             }
         }
+        StoreRequest::Download { app_id, device } => {
+            info!("Handling store download");
+            let device = check_device(device)?;
+            let contact_id = get_chat_contact(context, chat_id).await?;
+            let job = Job::Download {
+                msg_id: msg_id.to_u32(),
+                app_id,
+                contact_id: contact_id.map(|contact_id| contact_id.to_u32()),
+                device: Some(device),
+                request_id: request_id.map(str::to_string),
+            };
+            jobs::enqueue(state, &job).await?;
+        }
+        StoreRequest::Subscribe { app_id } => {
+            info!("Handling store subscribe");
+            handle_subscription(context, state, chat_id, msg_id, request_id, &app_id, true).await?;
+        }
+        StoreRequest::Unsubscribe { app_id } => {
+            info!("Handling store unsubscribe");
+            handle_subscription(context, state, chat_id, msg_id, request_id, &app_id, false)
+                .await?;
+        }
+        StoreRequest::Rate {
+            app_id,
+            stars,
+            comment,
+        } => {
+            info!("Handling store rating");
+            let contact_id = get_chat_contact(context, chat_id)
+                .await?
+                .context("Store chat has no contact")?;
+            let resp = handle_rate(state, contact_id, app_id, stars, comment).await;
+            send_response(context, msg_id, request_id, resp).await?;
+        }
+        StoreRequest::GetReviews { app_id } => {
+            info!("Handling store reviews request");
+            let reviews =
+                db::get_reviews(&mut *state.db.acquire().await?, &app_id, MAX_REVIEWS).await?;
+            send_response(
+                context,
+                msg_id,
+                request_id,
+                StoreResponse::Reviews { app_id, reviews },
+            )
+            .await?;
+        }
+        StoreRequest::Report { app_id, reason } => {
+            info!("Handling store report");
+            let contact_id = get_chat_contact(context, chat_id)
+                .await?
+                .context("Store chat has no contact")?;
+            let resp = match report(context, state, contact_id, &app_id, &reason).await {
+                Ok(()) => StoreResponse::ReportOkay { app_id },
+                Err(e) => {
+                    warn!("Error while handling report: {e:#}");
+                    StoreResponse::ReportError {
+                        error: StoreError::from(&e),
+                        app_id,
+                    }
+                }
+            };
+            send_response(context, msg_id, request_id, resp).await?;
+        }
+        StoreRequest::SetUpdatePolicy { policy } => {
+            info!("Handling store update policy");
+            let contact_id = get_chat_contact(context, chat_id)
+                .await?
+                .context("Store chat has no contact")?;
+            db::set_update_policy(&mut *state.db.acquire().await?, contact_id, policy).await?;
+            let response = StoreResponse::UpdatePolicy { policy };
+            send_response(context, msg_id, request_id, response).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Adds or removes an update subscription and responds with all subscriptions of the user.
+/// Only existing apps can be subscribed to.
+async fn handle_subscription(
+    context: &Context,
+    state: &State,
+    chat_id: ChatId,
+    msg_id: MsgId,
+    request_id: Option<&str>,
+    app_id: &str,
+    subscribe: bool,
+) -> anyhow::Result<()> {
+    let contact_id = get_chat_contact(context, chat_id)
+        .await?
+        .context("Store chat has no contact")?;
+    let conn = &mut *state.db.acquire().await?;
+    if subscribe {
+        if !db::visible_app_exists(conn, app_id).await? {
+            bail!(StoreError::NotFound);
+        }
+        db::add_subscription(conn, contact_id, app_id).await?;
     } else {
-        info!(
-            "Ignoring self-sent update: {}",
-            &update.get(..100.min(update.len())).unwrap_or_default()
-        )
+        db::remove_subscription(conn, contact_id, app_id).await?;
+    }
+    let app_ids = db::get_subscriptions(conn, contact_id).await?;
+    send_response(
+        context,
+        msg_id,
+        request_id,
+        StoreResponse::Subscriptions { app_ids },
+    )
+    .await?;
+    Ok(())
+}
+
+/// Handles a rating of the newest version of an app.
+async fn handle_rate(
+    state: &State,
+    contact_id: ContactId,
+    app_id: String,
+    stars: u8,
+    comment: Option<String>,
+) -> StoreResponse {
+    match rate(state, contact_id, &app_id, stars, comment).await {
+        Ok(rating) => StoreResponse::RateOkay { app_id, rating },
+        Err(e) => {
+            warn!("Error while handling rating: {e:#}");
+            StoreResponse::RateError {
+                error: StoreError::from(&e),
+                app_id,
+            }
+        }
+    }
+}
+
+async fn rate(
+    state: &State,
+    contact_id: ContactId,
+    app_id: &str,
+    stars: u8,
+    comment: Option<String>,
+) -> anyhow::Result<Rating> {
+    if !(1..=5).contains(&stars) {
+        bail!(StoreError::malformed(
+            "Rating must be between 1 and 5 stars"
+        ));
+    }
+    let comment = comment
+        .map(|comment| comment.trim().to_string())
+        .filter(|comment| !comment.is_empty());
+    if let Some(comment) = &comment {
+        if comment.chars().count() > MAX_COMMENT_LENGTH {
+            bail!(StoreError::malformed(format!(
+                "Review must not be longer than {MAX_COMMENT_LENGTH} characters"
+            )));
+        }
+    }
+
+    let conn = &mut *state.db.acquire().await?;
+    let app_info = db::get_app_info_for_app_id(conn, app_id)
+        .await
+        .with_context(|| format!("Unknown app {app_id}"))?;
+    let review = Review {
+        app_id: app_info.app_id,
+        version: app_info.version,
+        stars,
+        comment,
+        date: OffsetDateTime::now_utc().unix_timestamp(),
+        ..Default::default()
+    };
+    db::upsert_review(conn, contact_id, &review).await?;
+    db::get_rating(conn, &review.app_id)
+        .await?
+        .context("Rating vanished after review")
+}
+
+/// Stores a report about the newest version of an app and forwards it to the genesis group.
+async fn report(
+    context: &Context,
+    state: &State,
+    contact_id: ContactId,
+    app_id: &str,
+    reason: &str,
+) -> anyhow::Result<()> {
+    let reason = reason.trim();
+    if reason.is_empty() {
+        bail!(StoreError::malformed("Please describe the problem"));
+    }
+    if reason.chars().count() > MAX_COMMENT_LENGTH {
+        bail!(StoreError::malformed(format!(
+            "Report must not be longer than {MAX_COMMENT_LENGTH} characters"
+        )));
+    }
+
+    let conn = &mut *state.db.acquire().await?;
+    let app_info = db::get_app_info_for_app_id(conn, app_id)
+        .await
+        .with_context(|| format!("Unknown app {app_id}"))?;
+    let mut report = Report {
+        app_id: app_info.app_id,
+        version: app_info.version,
+        contact_id: contact_id.to_u32(),
+        reason: reason.to_string(),
+        timestamp: OffsetDateTime::now_utc().unix_timestamp(),
+        ..Default::default()
+    };
+    report.id = db::add_report(conn, &report).await?;
+
+    let reporter = Contact::get_by_id(context, contact_id)
+        .await?
+        .get_name_n_addr();
+    let id = report.id.to_string();
+    let text = fill(
+        catalog(None).report_received,
+        &[
+            &id,
+            &app_info.name,
+            &report.app_id,
+            &report.version.to_string(),
+            &reporter,
+            &report.reason,
+            &id,
+        ],
+    );
+    if let Err(e) = chat::send_text_msg(context, state.config.genesis_group, text).await {
+        warn!("Failed to forward report #{id} to the genesis group: {e:#}");
     }
     Ok(())
 }
 
-pub async fn handle_download(state: &State, app_id: String) -> StoreResponse {
+/// Handles a download request and records it for the statistics if the contact is known.
+/// The app is recorded as installed on `device`.
+pub async fn handle_download(
+    state: &State,
+    app_id: String,
+    contact_id: Option<ContactId>,
+    device: &str,
+) -> StoreResponse {
     match get_webxdc_data(state, &app_id).await {
-        Ok((data, name)) => StoreResponse::DownloadOkay { data, name, app_id },
+        Ok((data, app_info)) => {
+            info!("Sending {} version {}", app_info.app_id, app_info.version);
+            if let Some(contact_id) = contact_id {
+                if let Err(e) = record_download_for(state, &app_info, contact_id, device).await {
+                    warn!("Failed to record download of {app_id}: {e:#}");
+                }
+            }
+            StoreResponse::DownloadOkay {
+                data,
+                name: app_info.name,
+                app_id,
+            }
+        }
         Err(e) => {
-            warn!("Error while handling download request: {}", e);
+            warn!("Error while handling download request: {e:#}");
             StoreResponse::DownloadError {
-                error: e.to_string(),
+                error: StoreError::from(&e),
                 app_id,
             }
         }
     }
 }
 
+async fn record_download_for(
+    state: &State,
+    app_info: &AppInfo,
+    contact_id: ContactId,
+    device: &str,
+) -> anyhow::Result<()> {
+    record_download(
+        &mut *state.db.acquire().await?,
+        app_info,
+        contact_id,
+        device,
+    )
+    .await
+}
+
 /// Handles a request to download a store app.
-/// Returns the base64 encoded webxdc and the name of the app.
-async fn get_webxdc_data(state: &State, app_id: &str) -> anyhow::Result<(String, String)> {
+/// Returns the base64 encoded webxdc and the app info.
+async fn get_webxdc_data(state: &State, app_id: &str) -> anyhow::Result<(String, AppInfo)> {
     let app = db::get_app_info_for_app_id(&mut *state.db.acquire().await?, app_id).await?;
-    Ok((
-        encode(
-            &tokio::fs::read(
-                app.xdc_blob_path
-                    .to_str()
-                    .context("Can't covert file '{file:?}' to str")?,
-            )
-            .await?,
-        ),
-        app.name,
-    ))
+    let key = app.blob_key()?;
+    if !state.blob_store.exists(key).await? {
+        bail!(StoreError::VersionGone);
+    }
+    let data = state.blob_store.get(key).await?;
+    state.metrics.record_sent_xdc(data.len());
+    Ok((encode(&data), app))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let request = parse_request(r#"{"payload":{"Download":{"app_id":"poll"}}}"#);
+        assert!(matches!(
+            request,
+            Ok(Envelope {
+                request_id: None,
+                content: StoreRequest::Download { app_id, device: None },
+            }) if app_id == "poll"
+        ));
+
+        let request = parse_request(r#"{"payload":{"Download":{"app_id":"poll","device":"d1"}}}"#);
+        assert!(matches!(
+            request,
+            Ok(Envelope { content: StoreRequest::Download { device: Some(device), .. }, .. })
+                if device == "d1"
+        ));
+        assert!(check_device(Some("d".repeat(MAX_DEVICE_LENGTH + 1))).is_err());
+
+        let request =
+            parse_request(r#"{"payload":{"request_id":"7","Download":{"app_id":"poll"}}}"#);
+        assert!(matches!(
+            request,
+            Ok(Envelope { request_id: Some(id), .. }) if id == "7"
+        ));
+
+        let unknown = parse_request(r#"{"payload":{"type":"UpdateSent"}}"#);
+        assert!(matches!(unknown, Err(StoreError::Malformed { .. })));
+
+        let malformed = parse_request(r#"{"payload":{"Download":{}}}"#);
+        assert!(matches!(malformed, Err(StoreError::Malformed { .. })));
+        assert!(matches!(
+            parse_request("not json"),
+            Err(StoreError::Malformed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_store_error_from_anyhow() {
+        let error = anyhow::Error::new(sqlx::Error::RowNotFound).context("Unknown app");
+        assert_eq!(StoreError::from(&error), StoreError::NotFound);
+
+        let error = anyhow::Error::new(StoreError::VersionGone);
+        assert_eq!(StoreError::from(&error), StoreError::VersionGone);
+
+        let error = anyhow::anyhow!("Connection reset");
+        assert_eq!(StoreError::from(&error), StoreError::Internal);
+    }
+
+    #[test]
+    fn test_response_envelope() {
+        let response = StoreResponse::ReportOkay {
+            app_id: "poll".to_string(),
+        };
+        let json = serde_json::to_string(&Envelope::new(Some("7"), response));
+        assert_eq!(
+            json.unwrap_or_default(),
+            r#"{"request_id":"7","type":"ReportOkay","app_id":"poll"}"#
+        );
+
+        let json = serde_json::to_string(&Envelope::new(
+            None,
+            StoreResponse::Error {
+                error: StoreError::NotFound,
+            },
+        ));
+        assert_eq!(
+            json.unwrap_or_default(),
+            r#"{"type":"Error","error":{"kind":"NotFound"}}"#
+        );
+
+        let update = r#"{"payload":{"request_id":"7","Download":{}}}"#;
+        assert_eq!(get_request_id(update), Some("7".to_string()));
+        assert_eq!(get_request_id("not json"), None);
+    }
+
+    #[test]
+    fn test_store_error_serialization() {
+        let json = serde_json::to_string(&StoreError::malformed("Missing app_id"));
+        assert_eq!(
+            json.unwrap_or_default(),
+            r#"{"kind":"Malformed","reason":"Missing app_id"}"#
+        );
+    }
 }
@@ -0,0 +1,418 @@
+//! Bot settings loaded from an optional TOML config file.
+//!
+//! Every field has a default, so the bot can still be run with environment variables only.
+//! Environment variables always take precedence over values from the config file.
+
+use anyhow::{bail, Context as _, Result};
+use serde::Deserialize;
+use std::{
+    env,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{messages, utils::project_dirs};
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// Directory containing the databases, assets and imported xdcs.
+    /// Defaults to the per-user "XDC Store" config directory.
+    pub data_dir: PathBuf,
+
+    /// Directory containing the imported xdcs, relative paths are resolved against `data_dir`.
+    /// Defaults to `xdcs` in the data directory.
+    pub blob_root: Option<PathBuf>,
+
+    /// Text sent along with the store.xdc.
+    /// Replaces the localized default welcome message for all users if set.
+    pub welcome_message: Option<String>,
+
+    /// Size of the generated invite QR-code images in pixels.
+    pub qr_size: usize,
+
+    pub account: AccountSettings,
+    pub access: AccessSettings,
+    pub http: HttpSettings,
+    pub limits: Limits,
+    pub logging: LoggingSettings,
+    pub metrics: MetricsSettings,
+    pub notifications: NotificationSettings,
+    pub stats: StatsSettings,
+    pub storage: StorageSettings,
+    pub workers: WorkerSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            data_dir: PathBuf::new(),
+            blob_root: None,
+            welcome_message: None,
+            qr_size: 1024,
+            account: AccountSettings::default(),
+            access: AccessSettings::default(),
+            http: HttpSettings::default(),
+            limits: Limits::default(),
+            logging: LoggingSettings::default(),
+            metrics: MetricsSettings::default(),
+            notifications: NotificationSettings::default(),
+            stats: StatsSettings::default(),
+            storage: StorageSettings::default(),
+            workers: WorkerSettings::default(),
+        }
+    }
+}
+
+/// Credentials used to configure the Delta Chat account on first start.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountSettings {
+    pub addr: Option<String>,
+    pub mail_pw: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessMode {
+    /// Everybody who writes to the bot is served the store.
+    #[default]
+    Open,
+    /// Only contacts who joined with the invite QR-code are served the store.
+    InviteOnly,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessSettings {
+    pub mode: AccessMode,
+}
+
+/// HTTP API for dashboards and automation.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpSettings {
+    /// Address the HTTP API listens on, e.g. `127.0.0.1:8080`. The API is disabled if unset.
+    pub listen: Option<SocketAddr>,
+    /// Bearer token required for the admin endpoints, which are disabled if unset.
+    /// Overridden by `XDCSTORE_HTTP_TOKEN` if set.
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Maximum size of an importable xdc in bytes.
+    pub max_xdc_size: u64,
+    /// Maximum size of a bundle downloaded by `sync` in bytes.
+    pub max_bundle_size: u64,
+    /// Maximum number of webxdc requests a single chat may send per minute, `0` disables the limit.
+    pub requests_per_minute: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_xdc_size: 50 * 1024 * 1024,
+            max_bundle_size: 1024 * 1024 * 1024,
+            requests_per_minute: 60,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingSettings {
+    /// Default log filter in `env_logger` syntax, used if `RUST_LOG` is not set.
+    pub level: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsSettings {
+    /// Address health and metrics are served on, e.g. `127.0.0.1:9184`. Disabled if unset.
+    pub listen: Option<SocketAddr>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationSettings {
+    /// Send the new xdc along with update notifications instead of a text message only.
+    pub send_xdc: bool,
+    /// Seconds between checks for newly imported app versions.
+    pub interval: u64,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            send_xdc: false,
+            interval: 60,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatsSettings {
+    /// Include the total download count of every app in the app index sent to the frontend.
+    pub public_download_counts: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageBackend {
+    /// xdcs are stored in `blob_root`.
+    #[default]
+    Local,
+    /// xdcs are stored in an S3-compatible bucket.
+    S3,
+}
+
+/// Where the xdcs of the imported apps are stored.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSettings {
+    pub backend: StorageBackend,
+    /// Base URL of the S3 API, e.g. `https://s3.example.org`.
+    pub endpoint: Option<String>,
+    pub bucket: Option<String>,
+    pub region: String,
+    /// Prefix prepended to all object keys, e.g. `xdcs/`.
+    pub prefix: String,
+    /// Overridden by `XDCSTORE_S3_ACCESS_KEY` if set.
+    pub access_key: Option<String>,
+    /// Overridden by `XDCSTORE_S3_SECRET_KEY` if set.
+    pub secret_key: Option<String>,
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::Local,
+            endpoint: None,
+            bucket: None,
+            region: "us-east-1".to_string(),
+            prefix: String::new(),
+            access_key: None,
+            secret_key: None,
+        }
+    }
+}
+
+/// Parallel handling of the events of different chats.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkerSettings {
+    /// Number of workers, the events of one chat are always handled by the same worker in order.
+    pub count: usize,
+    /// Number of events queued per worker before new events have to wait.
+    pub queue_size: usize,
+}
+
+impl Default for WorkerSettings {
+    fn default() -> Self {
+        Self {
+            count: 4,
+            queue_size: 64,
+        }
+    }
+}
+
+impl Settings {
+    /// Loads the settings from the given config file and applies environment overrides.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let mut settings = match path {
+            Some(path) => {
+                let content = std::fs::read_to_string(path)
+                    .with_context(|| format!("failed to read config file {}", path.display()))?;
+                Self::from_toml(&content)
+                    .with_context(|| format!("failed to parse config file {}", path.display()))?
+            }
+            None => Self::default(),
+        };
+        settings.apply_env();
+
+        if settings.data_dir.as_os_str().is_empty() {
+            settings.data_dir = project_dirs()?.config_dir().to_path_buf();
+        }
+        Ok(settings)
+    }
+
+    pub fn from_toml(content: &str) -> Result<Self> {
+        Ok(toml::from_str(content)?)
+    }
+
+    fn apply_env(&mut self) {
+        if let Ok(addr) = env::var("addr") {
+            self.account.addr = Some(addr);
+        }
+        if let Ok(mail_pw) = env::var("mail_pw") {
+            self.account.mail_pw = Some(mail_pw);
+        }
+        if let Ok(data_dir) = env::var("XDCSTORE_DATA_DIR") {
+            self.data_dir = PathBuf::from(data_dir);
+        }
+        if let Ok(token) = env::var("XDCSTORE_HTTP_TOKEN") {
+            self.http.token = Some(token);
+        }
+        if let Ok(access_key) = env::var("XDCSTORE_S3_ACCESS_KEY") {
+            self.storage.access_key = Some(access_key);
+        }
+        if let Ok(secret_key) = env::var("XDCSTORE_S3_SECRET_KEY") {
+            self.storage.secret_key = Some(secret_key);
+        }
+    }
+
+    /// Returns the directory the blob paths of the app infos are relative to.
+    pub fn blob_root(&self) -> PathBuf {
+        match &self.blob_root {
+            Some(blob_root) => self.data_dir.join(blob_root),
+            None => self.data_dir.join("xdcs"),
+        }
+    }
+
+    /// Returns the text sent along with the store.xdc for the given locale.
+    pub fn welcome_message(&self, locale: Option<&str>) -> &str {
+        self.welcome_message
+            .as_deref()
+            .unwrap_or(messages::catalog(locale).welcome)
+    }
+
+    /// Checks the settings for values the bot can't work with.
+    pub fn validate(&self) -> Result<()> {
+        if self.data_dir.exists() && !self.data_dir.is_dir() {
+            bail!("data_dir {} is not a directory", self.data_dir.display());
+        }
+        let blob_root = self.blob_root();
+        if blob_root.exists() && !blob_root.is_dir() {
+            bail!("blob_root {} is not a directory", blob_root.display());
+        }
+        if self.storage.backend == StorageBackend::S3 {
+            for (name, value) in [
+                ("endpoint", &self.storage.endpoint),
+                ("bucket", &self.storage.bucket),
+                ("access_key", &self.storage.access_key),
+                ("secret_key", &self.storage.secret_key),
+            ] {
+                if value.is_none() {
+                    bail!("storage.{name} is required for the s3 backend");
+                }
+            }
+        }
+        if let Some(token) = &self.http.token {
+            if token.len() < 16 {
+                bail!("http.token must be at least 16 characters long");
+            }
+        }
+        if let Some(message) = &self.welcome_message {
+            if message.trim().is_empty() {
+                bail!("welcome_message must not be empty");
+            }
+        }
+        if !(21..=4096).contains(&self.qr_size) {
+            bail!("qr_size must be between 21 and 4096 pixels");
+        }
+        if let Some(addr) = &self.account.addr {
+            if !addr.contains('@') {
+                bail!("account.addr {addr:?} is not an email address");
+            }
+        }
+        if self.notifications.interval == 0 {
+            bail!("notifications.interval must be greater than 0");
+        }
+        if self.workers.count == 0 || self.workers.queue_size == 0 {
+            bail!("workers.count and workers.queue_size must be greater than 0");
+        }
+        if self.limits.max_xdc_size == 0 || self.limits.max_bundle_size == 0 {
+            bail!("limits.max_xdc_size and limits.max_bundle_size must be greater than 0");
+        }
+        if let Some(filter) = &self.logging.level {
+            // An optional `/regex` suffix filters the messages, not the levels
+            let directives = filter.split('/').next().unwrap_or_default();
+            // Directives without `=` are a level or a module logged at all levels
+            for (_, level) in directives.split(',').filter_map(|d| d.split_once('=')) {
+                let level = level.trim();
+                log::LevelFilter::from_str(level)
+                    .with_context(|| format!("invalid log level {level:?}"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_full_config() {
+        let settings = Settings::from_toml(
+            r#"
+data_dir = "/var/lib/xdcstore"
+blob_root = "apps"
+welcome_message = "Hello!"
+qr_size = 512
+
+[account]
+addr = "bot@example.org"
+mail_pw = "secret"
+
+[access]
+mode = "invite_only"
+
+[http]
+listen = "127.0.0.1:8080"
+token = "0123456789abcdef"
+
+[limits]
+max_xdc_size = 1000
+requests_per_minute = 0
+
+[logging]
+level = "xdcstore=debug"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(settings.data_dir, PathBuf::from("/var/lib/xdcstore"));
+        assert_eq!(
+            settings.blob_root(),
+            PathBuf::from("/var/lib/xdcstore/apps")
+        );
+        assert_eq!(settings.welcome_message(Some("de")), "Hello!");
+        assert_eq!(settings.access.mode, AccessMode::InviteOnly);
+        assert_eq!(
+            settings.http.listen,
+            Some(SocketAddr::from(([127, 0, 0, 1], 8080)))
+        );
+        assert_eq!(settings.limits.requests_per_minute, 0);
+        assert_eq!(settings.account.addr.as_deref(), Some("bot@example.org"));
+        settings.validate().unwrap();
+    }
+
+    #[test]
+    fn defaults_and_validation() {
+        let settings = Settings::from_toml("").unwrap();
+        assert_eq!(settings, Settings::default());
+
+        let settings = Settings::from_toml("[logging]\nlevel = \"info,xdcstore::db\"").unwrap();
+        settings.validate().unwrap();
+
+        let settings = Settings::from_toml("[logging]\nlevel = \"xdcstore=loud\"").unwrap();
+        assert!(settings.validate().is_err());
+
+        let settings = Settings::from_toml("[logging]\nlevel = \"warn,xdcstore=debug\"").unwrap();
+        settings.validate().unwrap();
+
+        assert!(Settings::from_toml("unknown_key = 1").is_err());
+
+        let settings = Settings::from_toml("[storage]\nbackend = \"s3\"").unwrap();
+        assert!(settings.validate().is_err());
+
+        let settings = Settings::from_toml("[http]\ntoken = \"short\"").unwrap();
+        assert!(settings.validate().is_err());
+    }
+}
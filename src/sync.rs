@@ -0,0 +1,294 @@
+//! Mirroring apps from the catalog or exported bundle of another store.
+
+use anyhow::{bail, Context as _, Result};
+use async_zip::tokio::read::fs::ZipFileReader;
+use log::warn;
+use reqwest::Url;
+use sqlx::SqliteConnection;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    blob_store::BlobStore,
+    bundle::Bundle,
+    catalog::{CatalogIndex, CATALOG_FILE, CATALOG_FORMAT},
+    db,
+    import::import_one,
+    request_handlers::AppInfo,
+    utils::{get_webxdc_manifest, is_valid_app_id, sha256_hex, AddType},
+};
+
+/// Maximum size of a downloaded catalog in bytes.
+const MAX_CATALOG_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Where the apps are mirrored from.
+enum Source {
+    /// A catalog, relative xdc URLs are resolved against `base`.
+    Catalog {
+        index: CatalogIndex,
+        base: Location,
+    },
+    Bundle(Bundle),
+}
+
+enum Location {
+    Url(Url),
+    Path(PathBuf),
+}
+
+pub struct SyncOptions {
+    /// Maximum size of a mirrored xdc in bytes.
+    pub max_size: u64,
+    /// Maximum size of a downloaded bundle in bytes.
+    pub max_bundle_size: u64,
+    /// Directory for temporary files.
+    pub tmp_dir: PathBuf,
+    /// Only print what would change.
+    pub dry_run: bool,
+}
+
+/// An app version missing in this store.
+struct Change {
+    app_id: String,
+    version: u32,
+    /// Whether the version is marked as critical update in the source.
+    critical: bool,
+    /// Whether an older version of the app exists.
+    update: bool,
+    /// Index of the app in the source.
+    index: usize,
+}
+
+/// Adds all app versions of the source which are newer than the ones in this store.
+/// `from` is a catalog URL, a catalog file or directory, or a bundle created with `export-store`.
+pub async fn sync(
+    conn: &mut SqliteConnection,
+    blob_store: &dyn BlobStore,
+    from: &str,
+    options: &SyncOptions,
+) -> Result<()> {
+    tokio::fs::create_dir_all(&options.tmp_dir).await?;
+    let result = sync_from(conn, blob_store, from, options).await;
+    if let Err(e) = tokio::fs::remove_dir_all(&options.tmp_dir).await {
+        warn!("Failed to remove {}: {e}", options.tmp_dir.display());
+    }
+    result
+}
+
+async fn sync_from(
+    conn: &mut SqliteConnection,
+    blob_store: &dyn BlobStore,
+    from: &str,
+    options: &SyncOptions,
+) -> Result<()> {
+    let source = Source::open(from, options).await?;
+
+    let mut changes = vec![];
+    let mut invalid = 0;
+    for (index, (app_id, version, critical)) in source.versions().into_iter().enumerate() {
+        if !is_valid_app_id(&app_id) {
+            eprintln!("Skipping version {version} of invalid app_id {app_id:?}");
+            invalid += 1;
+            continue;
+        }
+        if db::app_version_exists(conn, &app_id, version).await?
+            || db::maybe_get_greater_version(conn, &app_id, version).await?
+        {
+            continue;
+        }
+        changes.push(Change {
+            update: db::app_exists(conn, &app_id).await?,
+            app_id,
+            version,
+            critical,
+            index,
+        });
+    }
+
+    if changes.is_empty() && invalid == 0 {
+        println!("Already up to date");
+        return Ok(());
+    }
+
+    let mut failed = invalid;
+    for change in &changes {
+        if options.dry_run {
+            let action = if change.update { "update" } else { "add" };
+            println!(
+                "Would {action} {} version {}",
+                change.app_id, change.version
+            );
+            continue;
+        }
+        let action = match mirror(conn, blob_store, &source, change, options).await {
+            Ok(AddType::Added) => "Added",
+            Ok(AddType::Updated) => "Updated",
+            Ok(AddType::Ignored) => "Ignored",
+            Err(e) => {
+                eprintln!(
+                    "Failed to mirror {} version {}: {e:#}",
+                    change.app_id, change.version
+                );
+                failed += 1;
+                continue;
+            }
+        };
+        println!("{action} {} version {}", change.app_id, change.version);
+    }
+    if failed > 0 {
+        bail!(
+            "Failed to mirror {failed} of {} app versions",
+            changes.len() + invalid
+        );
+    }
+    Ok(())
+}
+
+/// Fetches and verifies the xdc of a change and imports it.
+async fn mirror(
+    conn: &mut SqliteConnection,
+    blob_store: &dyn BlobStore,
+    source: &Source,
+    change: &Change,
+    options: &SyncOptions,
+) -> Result<AddType> {
+    let data = source.fetch(change.index, options.max_size).await?;
+    let file = options.tmp_dir.join(format!("{}.xdc", sha256_hex(&data)));
+    tokio::fs::write(&file, data).await?;
+    let result = import_checked(conn, blob_store, &file, change, options).await;
+    tokio::fs::remove_file(&file).await?;
+    result
+}
+
+/// Imports an xdc if its manifest matches the app version listed in the source.
+async fn import_checked(
+    conn: &mut SqliteConnection,
+    blob_store: &dyn BlobStore,
+    file: &Path,
+    change: &Change,
+    options: &SyncOptions,
+) -> Result<AddType> {
+    let manifest = get_webxdc_manifest(&ZipFileReader::new(file).await?).await?;
+    if manifest.app_id != change.app_id || manifest.version != change.version {
+        bail!(
+            "xdc contains {} version {}, which does not match the source",
+            manifest.app_id,
+            manifest.version
+        );
+    }
+    import_one(file, blob_store, options.max_size, change.critical, conn).await
+}
+
+impl Source {
+    async fn open(from: &str, options: &SyncOptions) -> Result<Self> {
+        if from.starts_with("http://") || from.starts_with("https://") {
+            let url = Url::parse(from)?;
+            if url.path().ends_with(".zip") {
+                let archive = options.tmp_dir.join("bundle.zip");
+                let mut file = tokio::fs::File::create(&archive).await?;
+                download(url, options.max_bundle_size, &mut file).await?;
+                return Ok(Source::Bundle(Bundle::open(&archive).await?));
+            }
+            let mut content = Vec::new();
+            download(url.clone(), MAX_CATALOG_SIZE, &mut content).await?;
+            let index = parse_catalog(std::str::from_utf8(&content)?)?;
+            return Ok(Source::Catalog {
+                index,
+                base: Location::Url(url),
+            });
+        }
+
+        let mut path = PathBuf::from(from);
+        if path.is_dir() {
+            path.push(CATALOG_FILE);
+        }
+        if path.extension().map_or(false, |ext| ext == "zip") {
+            return Ok(Source::Bundle(Bundle::open(&path).await?));
+        }
+        let content = tokio::fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(Source::Catalog {
+            index: parse_catalog(&content)?,
+            base: Location::Path(path.parent().map(Path::to_path_buf).unwrap_or_default()),
+        })
+    }
+
+    /// Returns the app_id, version and critical mark of all app versions in the source.
+    fn versions(&self) -> Vec<(String, u32, bool)> {
+        let app_infos: Vec<&AppInfo> = match self {
+            Source::Catalog { index, .. } => index.apps.iter().map(|app| &app.app_info).collect(),
+            Source::Bundle(bundle) => bundle.apps.iter().map(|app| &app.app_info).collect(),
+        };
+        app_infos
+            .into_iter()
+            .map(|app_info| (app_info.app_id.clone(), app_info.version, app_info.critical))
+            .collect()
+    }
+
+    /// Fetches the xdc of the app version at `index` and verifies its hash.
+    async fn fetch(&self, index: usize, max_size: u64) -> Result<Vec<u8>> {
+        let data = match self {
+            Source::Catalog {
+                index: catalog,
+                base,
+            } => {
+                let app = catalog.apps.get(index).context("Invalid app index")?;
+                let data = match base {
+                    Location::Url(base) => {
+                        let mut data = Vec::new();
+                        download(base.join(&app.url)?, max_size, &mut data).await?;
+                        data
+                    }
+                    Location::Path(base) => tokio::fs::read(base.join(&app.url)).await?,
+                };
+                if sha256_hex(&data) != app.sha256 {
+                    bail!("Hash mismatch for {}", app.url);
+                }
+                data
+            }
+            Source::Bundle(bundle) => {
+                let app = bundle.apps.get(index).context("Invalid app index")?;
+                bundle.read_blob(app, max_size).await?
+            }
+        };
+        if u64::try_from(data.len())? > max_size {
+            bail!(
+                "xdc is {} bytes, which exceeds the limit of {max_size} bytes",
+                data.len()
+            );
+        }
+        Ok(data)
+    }
+}
+
+/// Downloads `url` into `out`, failing once the body exceeds `max_size` bytes.
+/// The announced length is checked first, but the body is counted while it is read
+/// as the server might not send a length or send more than it announced.
+async fn download(url: Url, max_size: u64, out: &mut (impl AsyncWrite + Unpin)) -> Result<()> {
+    let mut response = reqwest::get(url).await?.error_for_status()?;
+    if response
+        .content_length()
+        .map_or(false, |length| length > max_size)
+    {
+        bail!("{} exceeds the limit of {max_size} bytes", response.url());
+    }
+    let mut size = 0;
+    while let Some(chunk) = response.chunk().await? {
+        size += u64::try_from(chunk.len())?;
+        if size > max_size {
+            bail!("{} exceeds the limit of {max_size} bytes", response.url());
+        }
+        out.write_all(&chunk).await?;
+    }
+    out.flush().await?;
+    Ok(())
+}
+
+fn parse_catalog(content: &str) -> Result<CatalogIndex> {
+    let index: CatalogIndex = serde_json::from_str(content).context("Failed to parse catalog")?;
+    if index.format != CATALOG_FORMAT {
+        bail!("Unsupported catalog format {}", index.format);
+    }
+    Ok(index)
+}
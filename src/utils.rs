@@ -6,25 +6,32 @@ use std::io::Write;
 use anyhow::{bail, Context as _, Result};
 use async_zip::tokio::read::fs::ZipFileReader;
 use deltachat::{
+    blob::BlobObject,
     chat::{self, ChatId},
     config::Config,
+    contact::ContactId,
     context::Context,
     message::{Message, MsgId, Viewtype},
 };
 use directories::ProjectDirs;
 use futures::future::join_all;
 use serde::Serialize;
+use sha2::{Digest, Sha256};
 use sqlx::{SqliteConnection, Type};
 use std::{
-    env,
+    collections::BTreeMap,
     path::{Path, PathBuf},
 };
+use time::OffsetDateTime;
 use tokio::task::JoinHandle;
 
 use crate::{
     bot::State,
-    db,
-    request_handlers::{store::StoreResponse, AppInfo, WexbdcManifest},
+    db::{self, DownloadStats},
+    request_handlers::{
+        store::StoreResponse, AppInfo, AppStats, BotPayload, Envelope, UpdatePolicy, WexbdcManifest,
+    },
+    settings::AccountSettings,
     STORE_XDC,
 };
 
@@ -32,11 +39,18 @@ pub(crate) fn project_dirs() -> Result<ProjectDirs> {
     ProjectDirs::from("", "", "XDC Store").context("cannot determine home directory")
 }
 
-pub async fn configure_from_env(ctx: &Context) -> Result<()> {
-    let addr = env::var("addr").context("Missing environment variable addr")?;
-    ctx.set_config(Config::Addr, Some(&addr)).await?;
-    let pw = env::var("mail_pw").context("Missing environment variable mail_pw")?;
-    ctx.set_config(Config::MailPw, Some(&pw)).await?;
+/// Configures the deltachat account with the credentials from the config file or environment.
+pub async fn configure_account(ctx: &Context, account: &AccountSettings) -> Result<()> {
+    let addr = account
+        .addr
+        .as_deref()
+        .context("Missing account address, set the environment variable addr or account.addr in the config file")?;
+    ctx.set_config(Config::Addr, Some(addr)).await?;
+    let pw = account
+        .mail_pw
+        .as_deref()
+        .context("Missing account password, set the environment variable mail_pw or account.mail_pw in the config file")?;
+    ctx.set_config(Config::MailPw, Some(pw)).await?;
     ctx.set_config(Config::Bot, Some("1")).await?;
     ctx.set_config(Config::E2eeEnabled, Some("1")).await?;
     ctx.configure()
@@ -45,18 +59,26 @@ pub async fn configure_from_env(ctx: &Context) -> Result<()> {
     Ok(())
 }
 
-pub(crate) fn unpack_assets() -> Result<()> {
+pub(crate) fn unpack_assets(data_dir: &Path) -> Result<()> {
     let store_bytes = include_bytes!("../assets/store.xdc");
 
-    std::fs::create_dir_all(project_dirs()?.config_dir())?;
+    std::fs::create_dir_all(data_dir)?;
 
-    let store_path = Webxdc::Store.get_path().context("cannot get webxdc path")?;
+    let store_path = Webxdc::Store.get_path(data_dir);
     let mut file = File::create(&store_path)
         .with_context(|| format!("failed to create {}", store_path.display()))?;
     file.write_all(store_bytes)?;
     Ok(())
 }
 
+/// Returns the contact a 1:1 chat is held with.
+pub async fn get_chat_contact(context: &Context, chat_id: ChatId) -> Result<Option<ContactId>> {
+    Ok(chat::get_chat_contacts(context, chat_id)
+        .await?
+        .into_iter()
+        .find(|contact_id| !contact_id.is_special()))
+}
+
 /// Send a webxdc to a chat.
 pub async fn send_webxdc(
     context: &Context,
@@ -69,33 +91,227 @@ pub async fn send_webxdc(
     if let Some(text) = text {
         webxdc_msg.set_text(Some(text.to_string()));
     }
-    webxdc_msg.set_file(webxdc.get_str_path()?, None);
+    webxdc_msg.set_file(webxdc.get_str_path(&state.settings.data_dir)?, None);
     let msg_id = chat::send_msg(context, chat_id, &mut webxdc_msg).await?;
     let conn = &mut *state.db.acquire().await?;
     db::set_webxdc_version(conn, msg_id, state.webxdc_versions.get(webxdc), webxdc).await?;
     Ok(msg_id)
 }
 
-/// Sends a [deltachat::webxdc::StatusUpdateItem] with all [AppInfo]s greater than the given serial.
+/// Returns the preferred locale of the contact a 1:1 chat is held with.
+pub async fn get_chat_locale(
+    context: &Context,
+    conn: &mut SqliteConnection,
+    chat_id: ChatId,
+) -> Result<Option<String>> {
+    match get_chat_contact(context, chat_id).await? {
+        Some(contact_id) => Ok(db::get_contact_locale(conn, contact_id).await?),
+        None => Ok(None),
+    }
+}
+
+/// Sends the store.xdc with the welcome message and the current app index to a chat.
+pub async fn send_store_xdc(context: &Context, state: &State, chat_id: ChatId) -> Result<MsgId> {
+    let contact_id = get_chat_contact(context, chat_id).await?;
+    let locale = match contact_id {
+        Some(contact_id) => {
+            db::get_contact_locale(&mut *state.db.acquire().await?, contact_id).await?
+        }
+        None => None,
+    };
+    let msg = send_webxdc(
+        context,
+        state,
+        chat_id,
+        Webxdc::Store,
+        Some(state.settings.welcome_message(locale.as_deref())),
+    )
+    .await?;
+    send_newest_updates(context, state, msg, 0, vec![], contact_id, None).await?;
+    Ok(msg)
+}
+
+/// Sends a [deltachat::webxdc::StatusUpdateItem] with all [AppInfo]s greater than the given serial,
+/// the apps hidden since then and the current statistics of all apps.
 /// Updating tells the frontend which apps are going to receive an updated.
+/// Names and descriptions are localized to the locale of the contact,
+/// the apps the contact has installed on any device and the update policy are included.
+/// `request_id` is the ID of the `Update` request this responds to.
 pub async fn send_newest_updates(
     context: &Context,
+    state: &State,
     msg_id: MsgId,
-    db: &mut SqliteConnection,
     serial: u32,
     updating: Vec<String>,
+    contact_id: Option<ContactId>,
+    request_id: Option<&str>,
 ) -> anyhow::Result<()> {
-    let app_infos: Vec<_> = db::get_active_app_infos_since(db, serial).await?;
+    let db = &mut *state.db.acquire().await?;
+    let (locale, installed, update_policy) = match contact_id {
+        Some(contact_id) => (
+            db::get_contact_locale(db, contact_id).await?,
+            db::get_installed_apps(db, contact_id, None).await?,
+            db::get_update_policy(db, contact_id).await?,
+        ),
+        None => (None, vec![], UpdatePolicy::default()),
+    };
+    let mut app_infos: Vec<_> = db::get_active_app_infos_since(db, serial).await?;
+    if let Some(locale) = locale {
+        db::load_translations(db, &mut app_infos).await?;
+        for app_info in &mut app_infos {
+            app_info.localize(&locale);
+        }
+    }
+    let removed = db::get_hidden_app_ids_since(db, serial).await?;
+    let stats = get_app_stats(db, state.settings.stats.public_download_counts).await?;
     let serial = db::get_last_serial(db).await?;
     let resp = StoreResponse::Update {
         app_infos,
         serial,
         updating,
+        removed,
+        stats,
+        installed,
+        update_policy,
     };
-    send_update_payload_only(context, msg_id, resp).await?;
+    send_response(context, msg_id, request_id, resp).await?;
     Ok(())
 }
 
+/// Returns the download counts and ratings of all active apps.
+/// Download counts are only included if `downloads` is set.
+async fn get_app_stats(
+    conn: &mut SqliteConnection,
+    downloads: bool,
+) -> Result<BTreeMap<String, AppStats>> {
+    let counts = if downloads {
+        Some(db::get_download_counts(conn).await?)
+    } else {
+        None
+    };
+    let ratings = db::get_ratings(conn).await?;
+    Ok(db::get_active_app_ids(conn)
+        .await?
+        .into_iter()
+        .map(|app_id| {
+            let stats = AppStats {
+                downloads: counts
+                    .as_ref()
+                    .map(|counts| counts.get(&app_id).copied().unwrap_or_default()),
+                rating: ratings.get(&app_id).copied(),
+            };
+            (app_id, stats)
+        })
+        .collect())
+}
+
+/// Sends the xdc of an app from the blob store to a chat, optionally with a text.
+pub async fn send_app_xdc(
+    context: &Context,
+    state: &State,
+    chat_id: ChatId,
+    app_info: &AppInfo,
+    text: Option<String>,
+) -> Result<MsgId> {
+    let key = app_info.blob_key()?;
+    let data = state.blob_store.get(key).await?;
+    state.metrics.record_sent_xdc(data.len());
+    let name = Path::new(key)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or("app.xdc");
+    let blob = BlobObject::create(context, name, &data).await?;
+
+    let mut msg = Message::new(Viewtype::Webxdc);
+    msg.set_text(text);
+    msg.set_file(blob.as_name(), None);
+    chat::send_msg(context, chat_id, &mut msg).await
+}
+
+/// Returns whether an app_id only consists of `[A-Za-z0-9._-]`.
+/// App ids are used in file names, e.g. of exported xdcs, so others are rejected.
+pub fn is_valid_app_id(app_id: &str) -> bool {
+    !app_id.is_empty()
+        && app_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+/// Returns the hex encoded SHA-256 hash of some data.
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Returns the anonymised hash of a contact used in the download statistics.
+pub async fn contact_hash(conn: &mut SqliteConnection, contact_id: ContactId) -> Result<String> {
+    let salt =
+        db::get_or_create_stats_salt(conn, || format!("{:032x}", rand::random::<u128>())).await?;
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(contact_id.to_u32().to_be_bytes());
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Records a download of an app by a contact for the statistics and as installed app of a device.
+pub async fn record_download(
+    conn: &mut SqliteConnection,
+    app_info: &AppInfo,
+    contact_id: ContactId,
+    device: &str,
+) -> Result<()> {
+    let contact_hash = contact_hash(conn, contact_id).await?;
+    let timestamp = OffsetDateTime::now_utc().unix_timestamp();
+    db::record_download(
+        conn,
+        &app_info.app_id,
+        app_info.version,
+        &contact_hash,
+        timestamp,
+    )
+    .await?;
+    db::set_installed_app(
+        conn,
+        contact_id,
+        device,
+        &app_info.app_id,
+        app_info.version,
+        timestamp,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Formats a unix timestamp as `YYYY-MM-DD`.
+pub fn format_date(timestamp: i64) -> String {
+    match OffsetDateTime::from_unix_timestamp(timestamp) {
+        Ok(date) => format!(
+            "{}-{:02}-{:02}",
+            date.year(),
+            u8::from(date.month()),
+            date.day()
+        ),
+        Err(_) => String::new(),
+    }
+}
+
+/// Formats download statistics as a plain text table.
+pub fn format_download_stats(stats: &[DownloadStats], recent_days: i64) -> String {
+    let mut table = format!(
+        "{:<30} {:>10} {:>8} {:>10}\n",
+        "app_id",
+        "downloads",
+        "users",
+        format!("{recent_days} days")
+    );
+    for app in stats {
+        table.push_str(&format!(
+            "{:<30} {:>10} {:>8} {:>10}\n",
+            app.app_id, app.downloads, app.unique_users, app.recent_downloads
+        ));
+    }
+    table
+}
+
 pub async fn read_string(reader: &ZipFileReader, index: usize) -> anyhow::Result<String> {
     let mut entry = reader.reader_with_entry(index).await?;
     let mut data = String::new();
@@ -111,6 +327,7 @@ pub async fn read_vec(reader: &ZipFileReader, index: usize) -> anyhow::Result<Ve
 }
 
 /// Sends a [deltachat::webxdc::StatusUpdateItem] with only the given payload.
+/// The payload is marked as sent by the bot, see [BotPayload].
 pub async fn send_update_payload_only<T: Serialize>(
     context: &Context,
     msg_id: MsgId,
@@ -120,7 +337,7 @@ pub async fn send_update_payload_only<T: Serialize>(
         .send_webxdc_status_update_struct(
             msg_id,
             deltachat::webxdc::StatusUpdateItem {
-                payload: serde_json::to_value(payload)?,
+                payload: serde_json::to_value(BotPayload::new(payload))?,
                 ..Default::default()
             },
             "",
@@ -129,6 +346,16 @@ pub async fn send_update_payload_only<T: Serialize>(
     Ok(())
 }
 
+/// Sends the response to a request, echoing the ID of the request if it had one.
+pub async fn send_response<T: Serialize>(
+    context: &Context,
+    msg_id: MsgId,
+    request_id: Option<&str>,
+    response: T,
+) -> anyhow::Result<()> {
+    send_update_payload_only(context, msg_id, Envelope::new(request_id, response)).await
+}
+
 pub async fn get_webxdc_manifest(reader: &ZipFileReader) -> anyhow::Result<WexbdcManifest> {
     let entries = reader.file().entries();
     let manifest_index = entries
@@ -160,18 +387,17 @@ pub enum Webxdc {
 }
 
 impl Webxdc {
-    pub fn get_path(&self) -> Result<PathBuf> {
+    pub fn get_path(&self, data_dir: &Path) -> PathBuf {
         let filename = match self {
             Webxdc::Store => STORE_XDC,
         };
-        let path = project_dirs()?.config_dir().to_path_buf().join(filename);
-        Ok(path)
+        data_dir.join(filename)
     }
 
-    pub fn get_str_path(&self) -> Result<String> {
-        self.get_path()?
-            .to_str()
-            .with_context(|| format!("cannot convert path {:?} to string", self.get_path()))
+    pub fn get_str_path(&self, data_dir: &Path) -> Result<String> {
+        let path = self.get_path(data_dir);
+        path.to_str()
+            .with_context(|| format!("cannot convert path {path:?} to string"))
             .map(|str| str.to_string())
     }
 
@@ -199,9 +425,9 @@ impl WebxdcVersions {
     }
 }
 
-pub async fn read_webxdc_versions() -> anyhow::Result<WebxdcVersions> {
+pub async fn read_webxdc_versions(data_dir: &Path) -> anyhow::Result<WebxdcVersions> {
     for webxdc in Webxdc::iter() {
-        let webxdc_path = webxdc.get_path().context("cannot get webxdc path")?;
+        let webxdc_path = webxdc.get_path(data_dir);
         if !webxdc_path.try_exists()? {
             bail!("Required webxdc {} is not found.", webxdc_path.display());
         }
@@ -209,8 +435,9 @@ pub async fn read_webxdc_versions() -> anyhow::Result<WebxdcVersions> {
 
     let mut futures: Vec<JoinHandle<anyhow::Result<(Webxdc, u32)>>> = vec![];
     for webxdc in Webxdc::iter() {
+        let webxdc_path = webxdc.get_path(data_dir);
         futures.push(tokio::spawn(async move {
-            let version = get_webxdc_version(&webxdc.get_str_path()?).await?;
+            let version = get_webxdc_version(&webxdc_path).await?;
             Ok((webxdc, version))
         }))
     }
@@ -249,3 +476,17 @@ pub async fn maybe_upgrade_xdc(
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_app_id() {
+        assert!(is_valid_app_id("dc-calendar"));
+        assert!(is_valid_app_id("org.example_poll"));
+        assert!(!is_valid_app_id(""));
+        assert!(!is_valid_app_id("../../x"));
+        assert!(!is_valid_app_id("a/b"));
+    }
+}
@@ -0,0 +1,81 @@
+//! Worker pool handling the events of different chats in parallel.
+//!
+//! Every chat is assigned to one worker, so the events of a chat are still handled in order.
+
+use deltachat::{chat::ChatId, context::Context, EventType};
+use log::{info, warn};
+use std::sync::Arc;
+use tokio::{
+    sync::mpsc::{self, error::TrySendError},
+    task::JoinHandle,
+};
+
+use crate::bot::{Bot, State};
+
+pub struct WorkerPool {
+    queues: Vec<mpsc::Sender<EventType>>,
+    state: Arc<State>,
+}
+
+impl WorkerPool {
+    /// Spawns `count` workers with a queue of `queue_size` events each, at least one of both.
+    /// The workers stop once the pool is dropped and their queue is empty.
+    pub fn spawn(
+        context: &Context,
+        state: &Arc<State>,
+        count: usize,
+        queue_size: usize,
+    ) -> (Self, Vec<JoinHandle<()>>) {
+        let count = count.max(1);
+        let queue_size = queue_size.max(1);
+        let mut queues = Vec::with_capacity(count);
+        let mut handles = Vec::with_capacity(count);
+        for worker in 0..count {
+            let (sender, mut receiver) = mpsc::channel::<EventType>(queue_size);
+            let context = context.clone();
+            let state = state.clone();
+            handles.push(tokio::spawn(async move {
+                while let Some(event) = receiver.recv().await {
+                    state.metrics.record_dequeued();
+                    Bot::handle_event(&context, &state, event).await;
+                }
+                info!("Worker {worker} stopped");
+            }));
+            queues.push(sender);
+        }
+        (
+            Self {
+                queues,
+                state: state.clone(),
+            },
+            handles,
+        )
+    }
+
+    /// Queues an event for the worker of `chat_id`.
+    /// Waits for space in the queue if the worker is busy.
+    pub async fn dispatch(&self, chat_id: ChatId, event: EventType) {
+        let index = usize::try_from(chat_id.to_u32()).unwrap_or_default() % self.queues.len();
+        let Some(queue) = self.queues.get(index) else {
+            return;
+        };
+        self.state.metrics.record_enqueued();
+        let event = match queue.try_send(event) {
+            Ok(()) => return,
+            Err(TrySendError::Full(event)) => {
+                self.state.metrics.record_queue_full();
+                warn!("Queue of worker {index} is full, waiting");
+                event
+            }
+            Err(TrySendError::Closed(_)) => {
+                self.state.metrics.record_dequeued();
+                warn!("Worker {index} stopped, dropping event");
+                return;
+            }
+        };
+        if queue.send(event).await.is_err() {
+            self.state.metrics.record_dequeued();
+            warn!("Worker {index} stopped, dropping event");
+        }
+    }
+}
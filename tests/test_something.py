@@ -1,8 +1,12 @@
 import subprocess
 import base64
+import json
+import threading
 import zipfile
+from http.server import BaseHTTPRequestHandler, ThreadingHTTPServer
 from pathlib import Path
 from subprocess import Popen
+from urllib.parse import parse_qs, unquote, urlparse
 
 import pytest
 
@@ -28,27 +32,27 @@ def bot_assets_path():
 
 
 @pytest.fixture
-def bot_home_path(tmp_path_factory):
-    """HOME path for the bot."""
-    return tmp_path_factory.mktemp("bothome")
+def bot_data_path(tmp_path_factory):
+    """Data directory for the bot."""
+    return tmp_path_factory.mktemp("botdata")
 
 
 class BotProcess:
     addr: str
     process: Popen
 
-    def __init__(self, addr, password, binary_path, home_path):
+    def __init__(self, addr, password, binary_path, data_path):
         self.addr = addr
         self.password = password
         self.binary_path = binary_path
-        self.home_path = home_path
+        self.data_path = data_path
+        self.extra_args = []
 
     def start(self, **kwargs):
         self.process = Popen(
-            [self.binary_path, "start"],
+            [self.binary_path, "--data-dir", self.data_path, *self.extra_args, "start"],
             cwd=self.binary_path.parent,
             env={
-                "HOME": str(self.home_path),
                 "addr": self.addr,
                 "mail_pw": self.password,
                 "RUST_LOG": "xdcstore=info",
@@ -63,13 +67,15 @@ class BotProcess:
         subprocess.run(
             [
                 self.binary_path,
+                "--data-dir",
+                self.data_path,
+                *self.extra_args,
                 "import",
                 Path.cwd() / "example-xdcs",
             ],
             cwd=self.binary_path.parent,
             env={
                 "RUST_LOG": "xdcstore=info",
-                "HOME": str(self.home_path),
                 "addr": self.addr,
                 "mail_pw": self.password,
             },
@@ -81,12 +87,12 @@ class BotProcess:
 
 
 @pytest.fixture
-def storebot_stopped(acfactory, bot_home_path):
+def storebot_stopped(acfactory, bot_data_path):
     """Stopped store bot without any apps."""
     config = acfactory.get_next_liveconfig()
 
     return BotProcess(
-        config["addr"], config["mail_pw"], bot_binary_path(), bot_home_path
+        config["addr"], config["mail_pw"], bot_binary_path(), bot_data_path
     )
 
 
@@ -134,9 +140,14 @@ def test_update(acfactory, storebot):
     assert len(status_updates) == 1
     assert status_updates[0]["payload"] == {
         "type": "Update",
+        "from_bot": True,
         "app_infos": [],
         "serial": 0,
         "updating": [],
+        "removed": [],
+        "stats": {},
+        "installed": [],
+        "update_policy": "Auto",
     }
 
     # Request updates.
@@ -148,7 +159,48 @@ def test_update(acfactory, storebot):
     status_updates = msg_in.get_status_updates()
     assert len(status_updates) == 3
     payload = status_updates[-1]["payload"]
-    assert payload == {"type": "Update", "app_infos": [], "serial": 0, "updating": []}
+    assert payload == {
+        "type": "Update",
+        "from_bot": True,
+        "app_infos": [],
+        "serial": 0,
+        "updating": [],
+        "removed": [],
+        "stats": {},
+        "installed": [],
+        "update_policy": "Auto",
+    }
+
+
+def test_malformed_request(acfactory, storebot):
+    """Test that malformed requests get an error and the bot skips its own updates."""
+    (ac1,) = acfactory.get_online_accounts(1)
+
+    bot_contact = ac1.create_contact(storebot.addr)
+    bot_chat = bot_contact.create_chat()
+    bot_chat.send_text("hi!")
+
+    msg_in = ac1.wait_next_incoming_message()
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")
+
+    msg_in.send_status_update({"payload": {"request_id": "r1", "Unknown": {}}}, "")
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Self-sent
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Error response
+
+    payload = msg_in.get_status_updates()[2]["payload"]
+    assert payload["type"] == "Error"
+    assert payload["from_bot"]
+    assert payload["request_id"] == "r1"
+    assert payload["error"]["kind"] == "Malformed"
+
+    # The bot must not answer the echoes of its own responses.
+    msg_in.send_status_update({"payload": {"Update": {"serial": 0}}}, "")
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Self-sent
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Update response
+
+    status_updates = msg_in.get_status_updates()
+    assert len(status_updates) == 5
+    assert status_updates[4]["payload"]["type"] == "Update"
 
 
 def test_update_advanced(acfactory, storebot_example):
@@ -188,6 +240,66 @@ def test_update_advanced(acfactory, storebot_example):
     assert payload["app_id"] == "dc-calendar"
     assert payload["type"] == "DownloadOkay"
 
+    # The bot remembers the apps of the device, including the download.
+    msg_in.send_status_update({"payload": {"Update": {"serial": 0}}}, "update")
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Self-sent
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Update response
+    payload = msg_in.get_status_updates()[-1]["payload"]
+    assert payload["updating"] == []
+    installed = dict(payload["installed"])
+    assert installed.keys() == {"dc-calendar", "dc-hextris"}
+    assert installed["dc-calendar"] > 1
+
+    # A full report of the device forgets removed apps.
+    msg_in.send_status_update(
+        {"payload": {"Update": {"serial": 0, "apps": [("dc-hextris", 2)]}}},
+        "update",
+    )
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Self-sent
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Update response
+    payload = msg_in.get_status_updates()[-1]["payload"]
+    assert payload["installed"] == [["dc-hextris", 2]]
+
+
+def test_update_policy(acfactory, storebot_example):
+    """Test that updates are only offered if the user wants to be asked first."""
+    (ac1,) = acfactory.get_online_accounts(1)
+
+    bot_contact = ac1.create_contact(storebot_example.addr)
+    bot_chat = bot_contact.create_chat()
+    bot_chat.send_text("hi!")
+
+    msg_in = ac1.wait_next_incoming_message()
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")
+
+    msg_in.send_status_update({"payload": {"SetUpdatePolicy": {"policy": "Ask"}}}, "")
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Self-sent
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Policy response
+    payload = msg_in.get_status_updates()[-1]["payload"]
+    assert payload == {"type": "UpdatePolicy", "policy": "Ask", "from_bot": True}
+
+    # dc-calendar is outdated by 1 version
+    msg_in.send_status_update(
+        {"payload": {"Update": {"serial": 0, "apps": [("dc-calendar", 1)]}}},
+        "update",
+    )
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Self-sent
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Update response
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Offered updates
+
+    status_updates = msg_in.get_status_updates()
+    assert len(status_updates) == 6
+    payload = status_updates[4]["payload"]
+    assert payload["type"] == "Update"
+    assert payload["updating"] == []
+    assert payload["update_policy"] == "Ask"
+    payload = status_updates[5]["payload"]
+    assert payload == {
+        "type": "UpdatesAvailable",
+        "app_ids": ["dc-calendar"],
+        "from_bot": True,
+    }
+
 
 def test_import(acfactory, storebot_example):
     """Test that import works."""
@@ -208,6 +320,183 @@ def test_import(acfactory, storebot_example):
     assert len(app_infos) == 4
 
 
+def test_multiple_instances(acfactory, storebot_example, tmp_path_factory):
+    """Test that two stores with separate data directories run side by side."""
+    config = acfactory.get_next_liveconfig()
+    other_bot = BotProcess(
+        config["addr"],
+        config["mail_pw"],
+        bot_binary_path(),
+        tmp_path_factory.mktemp("otherbotdata"),
+    )
+    other_bot.start()
+    try:
+        (ac1,) = acfactory.get_online_accounts(1)
+
+        for bot, app_count in [(storebot_example, 4), (other_bot, 0)]:
+            bot_contact = ac1.create_contact(bot.addr)
+            bot_chat = bot_contact.create_chat()
+            bot_chat.send_text("hi!")
+
+            msg_in = ac1.wait_next_incoming_message()
+            ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")
+
+            status_updates = msg_in.get_status_updates()
+            assert len(status_updates[0]["payload"]["app_infos"]) == app_count
+    finally:
+        other_bot.stop()
+
+
+def test_export_import_store(acfactory, storebot_stopped, tmp_path_factory):
+    """Test that a store can be moved to another data directory with a bundle."""
+    storebot_stopped.install_examples()
+    archive = tmp_path_factory.mktemp("bundle") / "store.zip"
+    env = {"addr": storebot_stopped.addr, "mail_pw": storebot_stopped.password}
+    subprocess.run(
+        [bot_binary_path(), "--data-dir", storebot_stopped.data_path, "export-store", archive],
+        env=env,
+        check=True,
+    )
+
+    new_data_path = tmp_path_factory.mktemp("newbotdata")
+    subprocess.run(
+        [bot_binary_path(), "--data-dir", new_data_path, "import-store", archive],
+        env=env,
+        check=True,
+    )
+    subprocess.run(
+        [bot_binary_path(), "--data-dir", new_data_path, "fsck"],
+        env=env,
+        check=True,
+    )
+    storebot_stopped.data_path = new_data_path
+    storebot_stopped.start()
+
+    (ac1,) = acfactory.get_online_accounts(1)
+    bot_contact = ac1.create_contact(storebot_stopped.addr)
+    bot_chat = bot_contact.create_chat()
+    bot_chat.send_text("/get dc-calendar")
+    msg_in = ac1.wait_next_incoming_message()
+    assert msg_in.is_webxdc()
+
+
+def test_sync_from_bundle(storebot_stopped, tmp_path_factory):
+    """Test that a store can mirror the apps of another store's bundle."""
+    storebot_stopped.install_examples()
+    archive = tmp_path_factory.mktemp("bundle") / "store.zip"
+    env = {"addr": storebot_stopped.addr, "mail_pw": storebot_stopped.password}
+    subprocess.run(
+        [bot_binary_path(), "--data-dir", storebot_stopped.data_path, "export-store", archive],
+        env=env,
+        check=True,
+    )
+
+    mirror_data_path = tmp_path_factory.mktemp("mirrordata")
+    sync = [bot_binary_path(), "--data-dir", mirror_data_path, "sync", "--from", archive]
+    dry_run = subprocess.run(sync + ["--dry-run"], env=env, check=True, capture_output=True, text=True)
+    assert "Would add dc-calendar" in dry_run.stdout
+
+    result = subprocess.run(sync, env=env, check=True, capture_output=True, text=True)
+    assert "Added dc-calendar" in result.stdout
+    result = subprocess.run(sync, env=env, check=True, capture_output=True, text=True)
+    assert "Already up to date" in result.stdout
+
+
+def test_export_catalog(storebot_stopped, tmp_path_factory):
+    """Test that the static catalog lists the apps and can be mirrored."""
+    storebot_stopped.install_examples()
+    catalog_dir = tmp_path_factory.mktemp("catalog")
+    env = {"addr": storebot_stopped.addr, "mail_pw": storebot_stopped.password}
+    subprocess.run(
+        [bot_binary_path(), "--data-dir", storebot_stopped.data_path, "export-catalog", catalog_dir],
+        env=env,
+        check=True,
+    )
+    catalog = json.loads((catalog_dir / "catalog.json").read_text())
+    calendar = next(app for app in catalog["apps"] if app["app_id"] == "dc-calendar")
+    assert (catalog_dir / calendar["url"]).is_file()
+    assert calendar["url"] in (catalog_dir / "index.html").read_text()
+
+    mirror_data_path = tmp_path_factory.mktemp("mirrordata")
+    result = subprocess.run(
+        [bot_binary_path(), "--data-dir", mirror_data_path, "sync", "--from", catalog_dir],
+        env=env,
+        check=True,
+        capture_output=True,
+        text=True,
+    )
+    assert "Added dc-calendar" in result.stdout
+
+
+class S3StandIn(BaseHTTPRequestHandler):
+    """Minimal in-memory S3 API with path-style URLs, does not check signatures."""
+
+    objects = {}
+
+    def _key(self):
+        return unquote(urlparse(self.path).path)
+
+    def do_PUT(self):
+        length = int(self.headers.get("Content-Length", 0))
+        self.objects[self._key()] = self.rfile.read(length)
+        self.send_response(200)
+        self.end_headers()
+
+    def do_HEAD(self):
+        self.send_response(200 if self._key() in self.objects else 404)
+        self.end_headers()
+
+    def do_GET(self):
+        url = urlparse(self.path)
+        if "list-type" in parse_qs(url.query):
+            bucket = url.path.rstrip("/") + "/"
+            keys = "".join(
+                f"<Contents><Key>{key[len(bucket):]}</Key></Contents>"
+                for key in sorted(self.objects)
+                if key.startswith(bucket)
+            )
+            body = f"<ListBucketResult><IsTruncated>false</IsTruncated>{keys}</ListBucketResult>".encode()
+        elif self._key() in self.objects:
+            body = self.objects[self._key()]
+        else:
+            self.send_response(404)
+            self.end_headers()
+            return
+        self.send_response(200)
+        self.send_header("Content-Length", str(len(body)))
+        self.end_headers()
+        self.wfile.write(body)
+
+
+def test_s3_storage(acfactory, storebot_stopped, tmp_path):
+    """Test that apps can be stored in an S3-compatible bucket."""
+    server = ThreadingHTTPServer(("127.0.0.1", 0), S3StandIn)
+    threading.Thread(target=server.serve_forever, daemon=True).start()
+
+    config_path = tmp_path / "config.toml"
+    config_path.write_text(
+        f"""[storage]
+backend = "s3"
+endpoint = "http://127.0.0.1:{server.server_port}"
+bucket = "xdcstore"
+access_key = "access"
+secret_key = "secret"
+"""
+    )
+    storebot_stopped.extra_args = ["--config", config_path]
+    storebot_stopped.install_examples()
+    assert "/xdcstore/calendar.xdc" in S3StandIn.objects
+    storebot_stopped.start()
+
+    (ac1,) = acfactory.get_online_accounts(1)
+    bot_contact = ac1.create_contact(storebot_stopped.addr)
+    bot_chat = bot_contact.create_chat()
+    bot_chat.send_text("/get dc-calendar")
+    msg_in = ac1.wait_next_incoming_message()
+    assert msg_in.is_webxdc()
+    server.shutdown()
+
+
 def test_version(acfactory, storebot):
     """Test /version command."""
 
@@ -226,6 +515,30 @@ def test_version(acfactory, storebot):
     assert msg_in.text + "\n" == version_text
 
 
+def test_commands(acfactory, storebot_example):
+    """Test the text command interface."""
+    (ac1,) = acfactory.get_online_accounts(1)
+
+    bot_contact = ac1.create_contact(storebot_example.addr)
+    bot_chat = bot_contact.create_chat()
+
+    bot_chat.send_text("/help")
+    msg_in = ac1.wait_next_incoming_message()
+    assert "/search" in msg_in.text
+
+    bot_chat.send_text("/search calendar")
+    msg_in = ac1.wait_next_incoming_message()
+    assert "dc-calendar" in msg_in.text
+
+    bot_chat.send_text("/info xxx")
+    msg_in = ac1.wait_next_incoming_message()
+    assert "xxx" in msg_in.text
+
+    bot_chat.send_text("/get dc-calendar")
+    msg_in = ac1.wait_next_incoming_message()
+    assert msg_in.is_webxdc()
+
+
 def test_download(acfactory, storebot_example):
     """Test that download works."""
     (ac1,) = acfactory.get_online_accounts(1)
@@ -312,7 +625,7 @@ def test_frontend_update(acfactory, storebot):
     )  # Inital store hydration
     assert msg_in.is_webxdc()
 
-    update_manifest_version(storebot.home_path / ".config" / "xdcstore", 1000)
+    update_manifest_version(storebot.data_path, 1000)
 
     # Start the bot again to load the newer store.xdc version
     storebot.stop()
@@ -323,11 +636,20 @@ def test_frontend_update(acfactory, storebot):
     ac1._evtracker.get_matching(
         "DC_EVENT_WEBXDC_STATUS_UPDATE"
     )  # Update needed response
+    ac1._evtracker.get_matching(
+        "DC_EVENT_WEBXDC_STATUS_UPDATE"
+    )  # Update response, the request is still handled in degraded mode
 
     # Test that the bot sends an outdated response
     status_updates = msg_in.get_status_updates()
     payload = status_updates[2]["payload"]
-    assert payload == {"critical": True, "type": "Outdated", "version": 1000}
+    assert payload == {
+        "critical": False,
+        "type": "Outdated",
+        "version": 1000,
+        "from_bot": True,
+    }
+    assert status_updates[3]["payload"]["type"] == "Update"
 
     # In store.xdc the update button should send this message
     msg_in.send_status_update({"payload": {"type": "UpdateWebxdc"}}, "")
@@ -337,9 +659,55 @@ def test_frontend_update(acfactory, storebot):
 
     # Test that the bot sends an a download confirmation
     status_updates = msg_in.get_status_updates()
-    payload = status_updates[4]["payload"]
-    assert payload == {"type": "UpdateSent"}
+    payload = status_updates[5]["payload"]
+    assert payload == {"type": "UpdateSent", "from_bot": True}
 
     # Test that the bots sends a new version of the store
     msg_in = ac1.wait_next_incoming_message()
     assert msg_in.is_webxdc()
+
+
+def test_handshake(acfactory, storebot):
+    """Test that frontends with handshake learn about updates from the Welcome."""
+    (ac1,) = acfactory.get_online_accounts(1)
+
+    bot_contact = ac1.create_contact(storebot.addr)
+    bot_chat = bot_contact.create_chat()
+    bot_chat.send_text("hi!")
+
+    msg_in = ac1.wait_next_incoming_message()
+    ac1._evtracker.get_matching(
+        "DC_EVENT_WEBXDC_STATUS_UPDATE"
+    )  # Inital store hydration
+
+    hello = {"payload": {"type": "Hello", "protocol_version": 2, "capabilities": []}}
+    msg_in.send_status_update(hello, "")
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Self-sent
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Welcome response
+
+    payload = msg_in.get_status_updates()[2]["payload"]
+    assert payload["type"] == "Welcome"
+    assert payload["protocol_version"] == 2
+    assert "RequestIds" in payload["features"]
+    assert payload["update"] is None
+
+    update_manifest_version(storebot.data_path, 1000)
+    storebot.stop()
+    storebot.start(XDCSTORE_KEEP_ASSETS="yes")
+
+    msg_in.send_status_update(hello, "")
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Self-sent
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Welcome response
+
+    payload = msg_in.get_status_updates()[4]["payload"]
+    assert payload["type"] == "Welcome"
+    assert payload["update"] == {"version": 1000, "critical": False}
+
+    # Requests of frontends with handshake get no Outdated response.
+    msg_in.send_status_update({"payload": {"Update": {"serial": 0}}}, "")
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Self-sent
+    ac1._evtracker.get_matching("DC_EVENT_WEBXDC_STATUS_UPDATE")  # Update response
+
+    status_updates = msg_in.get_status_updates()
+    assert len(status_updates) == 7
+    assert status_updates[6]["payload"]["type"] == "Update"